@@ -2,7 +2,12 @@ use std::collections::HashMap;
 
 use egui::ImageSource;
 use emath::{Pos2, Vec2};
-use turtlicoscript::{ast::Spanned, parser, tokens::Token};
+use turtlicoscript::{
+    ast::Spanned,
+    error::{CallFrame, Error},
+    locale::{Language, Message},
+    parser,
+};
 use turtlicoscript_gui::{
     app::{ScriptApp, ScriptState, SubApp},
     t_log, world::WorldCreationData,
@@ -13,7 +18,7 @@ use crate::{
     cmdrenderer::CMD_SIZE_VEC,
     dndctl::{DnDCtl, DragAction, DragData},
     nativedialogs, programview,
-    project::{Command, CommandRange, Project},
+    project::{Command, CommandRange, Project, TokenCells},
     widgets::{self, BTN_ICON_SIZE, MARGIN_MEDIUM, MARGIN_SMALL},
 };
 
@@ -42,7 +47,15 @@ pub struct EditorApp {
     cmdpalette_state: cmdpalette::CmdPaletteState,
 
     script_subapp: Option<ScriptApp>,
-    script_errors: Option<Vec<Spanned<turtlicoscript::error::Error>>>,
+    script_errors: Option<Vec<Spanned<Error>>>,
+    /// Problems of the program that did not stop it
+    script_warnings: Vec<Spanned<Error>>,
+    /// Function calls that led to the runtime error
+    script_error_trace: Vec<CallFrame>,
+    /// Icons of the tokens of the running program, spans of its errors are token indices
+    script_token_cells: Option<TokenCells>,
+    /// Language of error messages
+    language: Language,
 
     project_autosave_time: chrono::DateTime<chrono::Local>,
     project_path: Option<std::path::PathBuf>,
@@ -111,6 +124,10 @@ impl EditorApp {
             cmdpalette_state,
             script_subapp: None,
             script_errors: None,
+            script_warnings: vec![],
+            script_error_trace: vec![],
+            script_token_cells: None,
+            language: Language::default(),
             project_autosave_time: chrono::DateTime::<chrono::Local>::MIN_UTC.into(),
             project_path: None,
             project_save_time: chrono::DateTime::<chrono::Local>::MIN_UTC.into(),
@@ -231,6 +248,15 @@ impl EditorApp {
                     }
                 }
 
+                // Language of messages
+                egui::ComboBox::from_id_salt("language")
+                    .selected_text(self.language.name())
+                    .show_ui(ui, |ui| {
+                        for lang in Language::ALL {
+                            ui.selectable_value(&mut self.language, lang, lang.name());
+                        }
+                    });
+
                 ui.with_layout(
                     egui::Layout::centered_and_justified(egui::Direction::RightToLeft),
                     |ui| {
@@ -264,17 +290,34 @@ impl EditorApp {
                     true,
                 ));
                 ui.vertical(|ui| {
-                    if let Some(errors) = self.script_errors.clone() {
-                        let errors = errors
-                            .into_iter()
-                            .map(|i| i.item.to_string())
+                    if self.script_errors.is_some() || !self.script_warnings.is_empty() {
+                        let warning = Message::Warning.text(self.language);
+                        let mut errors = self
+                            .script_warnings
+                            .iter()
+                            .map(|i| format!("{}: {}", warning, i.item.message(self.language)))
+                            .chain(
+                                self.script_errors
+                                    .iter()
+                                    .flatten()
+                                    .map(|i| i.item.message(self.language)),
+                            )
                             .collect::<Vec<String>>()
                             .join("\n");
+                        if !self.script_error_trace.is_empty() {
+                            errors.push('\n');
+                            errors.push_str(Message::CallStack.text(self.language));
+                            for frame in self.script_error_trace.iter() {
+                                errors.push_str(&format!("\n  {}", frame.name));
+                            }
+                        }
                         widgets::error_frame(
                             ui,
                             &errors,
                             || {
                                 self.script_errors = None;
+                                self.script_warnings.clear();
+                                self.programview_state.error_cells.clear();
                             },
                             self.icons.get("close").unwrap(),
                         );
@@ -349,19 +392,11 @@ impl EditorApp {
             return;
         }
         self.script_errors = None;
-        let tokens = self
-            .programview_state
-            .project
-            .borrow()
-            .program
-            .clone()
-            .into_iter()
-            .flatten()
-            .filter_map(|item| match item {
-                Command::Token(token) => Some(token),
-                _ => None,
-            })
-            .collect::<Vec<Token>>();
+        self.script_warnings.clear();
+        self.script_error_trace.clear();
+        self.programview_state.error_cells.clear();
+        let (tokens, cells) = self.programview_state.project.borrow().tokens();
+        self.script_token_cells = Some(cells);
         match parser::parse_tokens(tokens) {
             Ok(ast) => {
                 println!("AST: {:?}", ast);
@@ -374,11 +409,30 @@ impl EditorApp {
                 self.script_subapp = Some(subapp);
             }
             Err(errors) => {
-                self.script_errors = Some(errors);
+                self.show_script_errors(errors, vec![]);
             }
         }
     }
 
+    fn show_script_errors(&mut self, errors: Vec<Spanned<Error>>, trace: Vec<CallFrame>) {
+        if let Some(cells) = &self.script_token_cells {
+            self.programview_state
+                .error_cells
+                .extend(errors.iter().flat_map(|err| cells.cells(&err.span)));
+        }
+        self.script_errors = Some(errors);
+        self.script_error_trace = trace;
+    }
+
+    fn show_script_warnings(&mut self, warnings: Vec<Spanned<Error>>) {
+        if let Some(cells) = &self.script_token_cells {
+            self.programview_state
+                .error_cells
+                .extend(warnings.iter().flat_map(|warning| cells.cells(&warning.span)));
+        }
+        self.script_warnings.extend(warnings);
+    }
+
     fn local_save(&mut self, save_as: bool) {
         if self.save_file_receiver.is_some() {
             return;
@@ -441,18 +495,29 @@ impl SubApp for EditorApp {
             .show(ctx, |ui| {
                 self.ui(ui, frame);
             });
+        // Warnings are found before the program starts, so they are shown while it runs
+        let warnings = self
+            .script_subapp
+            .as_ref()
+            .map(|subapp| std::mem::take(&mut *subapp.warnings.lock().unwrap()))
+            .unwrap_or_default();
+        if !warnings.is_empty() {
+            self.show_script_warnings(warnings);
+        }
         if let Some(code_subapp) = self.script_subapp.as_mut() {
             let app_continues = code_subapp.update(ctx, frame);
             if !app_continues {
-                if let ScriptState::Error(err) = &*code_subapp.program_state.lock().unwrap() {
-                    match err.item {
-                        turtlicoscript::error::Error::Interrupted => {}
-                        _ => {
-                            self.script_errors = Some(vec![err.clone()]);
-                        }
-                    }
-                }
+                let error = match &*code_subapp.program_state.lock().unwrap() {
+                    ScriptState::Error(err, trace) => match err.item {
+                        Error::Interrupted => None,
+                        _ => Some((err.clone(), trace.clone())),
+                    },
+                    _ => None,
+                };
                 self.script_subapp = None;
+                if let Some((err, trace)) = error {
+                    self.show_script_errors(vec![err], trace);
+                }
             }
         }
         true
@@ -460,10 +525,14 @@ impl SubApp for EditorApp {
 
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         self.autosave(storage);
+        storage.set_string("language", self.language.code().to_owned());
     }
 
     fn load(&mut self, storage: &dyn eframe::Storage) {
         self.autosave_load(storage);
+        if let Some(lang) = storage.get_string("language").as_deref().and_then(Language::from_code) {
+            self.language = lang;
+        }
     }
 }
 
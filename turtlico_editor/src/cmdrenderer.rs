@@ -1,7 +1,7 @@
 use std::{collections::HashMap, sync::Arc};
 use egui::load::SizedTexture;
 use emath::{Rect, Vec2, Pos2};
-use turtlicoscript::tokens::Token;
+use turtlicoscript::tokens::{escape_string, Token};
 
 use crate::project::{Command, Project};
 
@@ -134,7 +134,9 @@ impl CommandRenderer {
                     },
                     Token::String(value) => {
                         background = self.color_string;
-                        text = Some(value);
+                        // Special characters are shown as escape sequences, empty strings still get a label
+                        text_owned = escape_string(value);
+                        text = Some("");
                         color_text = self.color_text_dark;
                     },
                     Token::Integer(value) => {
@@ -292,6 +294,7 @@ fn load_vars_icons(ctx: &egui::Context) -> HashMap<String, SizedTexture> {
 fn load_token_icons(ctx: &egui::Context) -> HashMap<Token, SizedTexture> {
     let mut map = HashMap::new();
     insert_token_icon_emmbeded!(map, Token::Break, "../icons/break.svg", ctx);
+    insert_token_icon_emmbeded!(map, Token::Continue, "../icons/continue.svg", ctx);
     insert_token_icon_emmbeded!(map, Token::FnDef, "../icons/fndef.svg", ctx);
     insert_token_icon_emmbeded!(map, Token::For, "../icons/for.svg", ctx);
     insert_token_icon_emmbeded!(map, Token::If, "../icons/if.svg", ctx);
@@ -304,25 +307,40 @@ fn load_token_icons(ctx: &egui::Context) -> HashMap<Token, SizedTexture> {
 
 fn token_text_icons() -> HashMap<Token, &'static str> {
     let mut map = HashMap::new();
+    map.insert(Token::And, "and");
     map.insert(Token::Assignment, "=");
     map.insert(Token::Colon, ":");
     map.insert(Token::Comma, ",");
     map.insert(Token::Dot, ".");
+    map.insert(Token::DoubleSlash, "//");
+    map.insert(Token::DoubleStar, "**");
     map.insert(Token::Eq, "==");
+    map.insert(Token::False, "false");
+    map.insert(Token::Global, "global");
     map.insert(Token::Gt, ">");
     map.insert(Token::Gte, ">=");
+    map.insert(Token::In, "in");
     map.insert(Token::LeftCurly, "{");
     map.insert(Token::LeftParent, "(");
     map.insert(Token::LeftSquare, "[");
+    map.insert(Token::Local, "local");
     map.insert(Token::Lt, "<");
     map.insert(Token::Lte, "<=");
     map.insert(Token::Minus, "-");
+    map.insert(Token::MinusAssignment, "-=");
     map.insert(Token::Neq, "!=");
+    map.insert(Token::Not, "not");
+    map.insert(Token::Or, "or");
+    map.insert(Token::Percent, "%");
     map.insert(Token::Plus, "+");
+    map.insert(Token::PlusAssignment, "+=");
     map.insert(Token::RightCurly, "}");
     map.insert(Token::RightParent, ")");
     map.insert(Token::RightSquare, "]");
     map.insert(Token::Slash, "/");
+    map.insert(Token::SlashAssignment, "/=");
     map.insert(Token::Star, "*");
+    map.insert(Token::StarAssignment, "*=");
+    map.insert(Token::True, "true");
     map
 }
\ No newline at end of file
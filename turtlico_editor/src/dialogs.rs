@@ -1,4 +1,4 @@
-use turtlicoscript::tokens::Token;
+use turtlicoscript::tokens::{escape_string, unescape_string, Token};
 
 use crate::{
     cmdpalette::CmdPaletteState,
@@ -41,16 +41,28 @@ pub fn programview_dialog(ui: &mut egui::Ui, state: &mut ProgramViewState) {
                 }
             }
             Command::Token(token) => match token {
+                // The value is edited with escape sequences, see edited_command
                 Token::String(val) => {
                     let mut new_value = val.to_string();
+                    let decoded = unescape_string(&new_value);
+                    let error_color = ui.visuals().error_fg_color;
                     match show_dialog(ui, "Enter a string", DialogSizeMode::RegularSize, |ui: &mut egui::Ui| {
-                        ui.add(egui::TextEdit::singleline(&mut new_value))
-                    }) {
-                        DialogResult::Apply => {
-                            state.project.borrow_mut().program[edited_cmd.1][edited_cmd.0] =
-                                Command::Token(Token::String(new_value));
-                            state.edited_cmd = None;
+                        let mut text_edit = egui::TextEdit::singleline(&mut new_value);
+                        if decoded.is_err() {
+                            text_edit = text_edit.text_color(error_color);
                         }
+                        ui.add(text_edit)
+                    }) {
+                        DialogResult::Apply => match unescape_string(&new_value) {
+                            Ok(decoded) => {
+                                state.project.borrow_mut().program[edited_cmd.1][edited_cmd.0] =
+                                    Command::Token(Token::String(decoded));
+                                state.edited_cmd = None;
+                            }
+                            Err(_) => {
+                                edited_cmd.2 = Command::Token(Token::String(new_value));
+                            }
+                        },
                         DialogResult::Cancel => {
                             state.edited_cmd = None;
                         }
@@ -168,6 +180,15 @@ pub fn cmdpallete_dialog(
     }
 }
 
+/// Converts a command to the form that is edited by `programview_dialog`.
+/// Strings are edited with escape sequences so that special characters can be typed.
+pub fn edited_command(cmd: &Command) -> Command {
+    match cmd {
+        Command::Token(Token::String(val)) => Command::Token(Token::String(escape_string(val))),
+        cmd => cmd.clone(),
+    }
+}
+
 pub fn show_dialog<F: FnOnce(&mut egui::Ui) -> egui::Response>(
     ui: &mut egui::Ui,
     title: &str,
@@ -15,6 +15,8 @@ pub struct ProgramViewState {
     project_modify_timestamp: chrono::DateTime<chrono::Local>,
     layout: Vec<Vec<f32>>,
     pub(crate) edited_cmd: Option<(usize, usize, Command)>,
+    /// (col, row) of the icons that caused the last error, cleared when the program changes
+    pub error_cells: Vec<(usize, usize)>,
 }
 
 impl ProgramViewState {
@@ -27,6 +29,7 @@ impl ProgramViewState {
             project_modify_timestamp: chrono::Local::now(),
             layout: vec![],
             edited_cmd: None,
+            error_cells: vec![],
         }
     }
 
@@ -129,6 +132,7 @@ fn programview_ui(
     if project_modify_timestamp != state.project_modify_timestamp {
         state.recalc_layout(ui);
         state.project_modify_timestamp = project_modify_timestamp;
+        state.error_cells.clear();
     }
 
     let available_space = ui.available_size();
@@ -199,8 +203,11 @@ fn programview_ui(
 
                         if response.secondary_clicked() {
                             if let Some((cmd, range)) = state.get_cmd_at_pointer(ui, rect) {
-                                state.edited_cmd =
-                                    Some((range.start.0, range.start.1, cmd.clone()));
+                                state.edited_cmd = Some((
+                                    range.start.0,
+                                    range.start.1,
+                                    dialogs::edited_command(&cmd),
+                                ));
                             }
                         }
                     }
@@ -225,6 +232,7 @@ fn programview_ui(
                             rect.min,
                             None,
                         );
+                        highlight_error_cells(ui, state, &painter, rect.min);
                     }
                 });
             })
@@ -232,6 +240,30 @@ fn programview_ui(
     })
 }
 
+fn highlight_error_cells(
+    ui: &egui::Ui,
+    state: &ProgramViewState,
+    painter: &egui::Painter,
+    pos: Pos2,
+) {
+    let stroke = egui::Stroke::new(2.0, ui.visuals().error_fg_color);
+    for (col, row) in state.error_cells.iter().copied() {
+        let Some(line_layout) = state.layout.get(row) else {
+            continue;
+        };
+        let Some(width) = line_layout.get(col) else {
+            continue;
+        };
+        let x: f32 = line_layout[..col].iter().sum();
+        let min = pos + Vec2::new(x, row as f32 * CMD_SIZE_VEC.y);
+        painter.rect_stroke(
+            Rect::from_min_size(min, Vec2::new(*width, CMD_SIZE_VEC.y)),
+            2.0,
+            stroke,
+        );
+    }
+}
+
 pub fn programview<'a>(
     state: &'a mut ProgramViewState,
     dndctl: &'a mut DnDCtl<EditorDragData>,
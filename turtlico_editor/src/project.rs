@@ -1,6 +1,9 @@
 use egui::load::SizedTexture;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
 
 use turtlicoscript::tokens::Token;
 use turtlicoscript_gui::{
@@ -84,6 +87,13 @@ pub struct CommandRange {
     pub end: (usize, usize),
 }
 
+/// Icons of the tokens that the program passes to the parser.
+/// Spans of errors are token indices, see [`Project::tokens`].
+pub struct TokenCells {
+    /// (col, row) of every token
+    cells: Vec<(usize, usize)>,
+}
+
 impl Project {
     pub fn empty(ctx: &egui::Context) -> Self {
         Self {
@@ -119,6 +129,22 @@ impl Project {
         serde_json::to_string(self)
     }
 
+    /// Tokens of the program for [`turtlicoscript::parser::parse_tokens`] without comments.
+    /// The token with index `i` gets the span `i..i + 1`.
+    pub fn tokens(&self) -> (Vec<Token>, TokenCells) {
+        let mut tokens = vec![];
+        let mut cells = vec![];
+        for (row, line) in self.program.iter().enumerate() {
+            for (col, cmd) in line.iter().enumerate() {
+                if let Command::Token(token) = cmd {
+                    tokens.push(token.clone());
+                    cells.push((col, row));
+                }
+            }
+        }
+        (tokens, TokenCells { cells })
+    }
+
     fn insert_single(&mut self, cmd: Command, mut col: usize, mut row: usize, extra_insert: bool) {
         if row >= self.program.len() {
             self.program.push(vec![Command::Token(Token::Newline)]);
@@ -246,6 +272,19 @@ impl Project {
     }
 }
 
+impl TokenCells {
+    /// Icons covered by a span of tokens.
+    /// Spans after the last token (the end of the program) return the last icon.
+    pub fn cells(&self, span: &Range<usize>) -> Vec<(usize, usize)> {
+        let Some(last) = self.cells.len().checked_sub(1) else {
+            return vec![];
+        };
+        let start = span.start.min(last);
+        let end = span.end.clamp(start + 1, self.cells.len());
+        self.cells[start..end].to_vec()
+    }
+}
+
 impl CommandRange {
     pub fn single_icon(col: usize, row: usize) -> Self {
         Self {
@@ -293,8 +332,10 @@ fn get_cmd_plugins(ctx: &egui::Context) -> Vec<Plugin> {
         commands: vec![
             Command::Token(Token::Loop),
             Command::Token(Token::For),
+            Command::Token(Token::In),
             Command::Token(Token::While),
             Command::Token(Token::Break),
+            Command::Token(Token::Continue),
             Command::Token(Token::If),
             Command::Token(Token::Else),
             Command::Token(Token::FnDef),
@@ -302,10 +343,18 @@ fn get_cmd_plugins(ctx: &egui::Context) -> Vec<Plugin> {
             Command::Token(Token::Variable("x".to_owned())),
             Command::Token(Token::Function("".to_owned())),
             Command::Token(Token::Assignment),
+            Command::Token(Token::PlusAssignment),
+            Command::Token(Token::MinusAssignment),
+            Command::Token(Token::StarAssignment),
+            Command::Token(Token::SlashAssignment),
+            Command::Token(Token::Local),
+            Command::Token(Token::Global),
             Command::Token(Token::Dot),
             Command::Token(Token::String("str".to_owned())),
             Command::Token(Token::Integer(0)),
             Command::Token(Token::Float("0.0".to_owned())),
+            Command::Token(Token::True),
+            Command::Token(Token::False),
             Command::Token(Token::Colon),
             Command::Token(Token::LeftCurly),
             Command::Token(Token::RightCurly),
@@ -315,6 +364,9 @@ fn get_cmd_plugins(ctx: &egui::Context) -> Vec<Plugin> {
             Command::Token(Token::Minus),
             Command::Token(Token::Star),
             Command::Token(Token::Slash),
+            Command::Token(Token::DoubleSlash),
+            Command::Token(Token::Percent),
+            Command::Token(Token::DoubleStar),
             Command::Token(Token::Lt),
             Command::Token(Token::Gt),
             Command::Token(Token::Lte),
@@ -323,6 +375,9 @@ fn get_cmd_plugins(ctx: &egui::Context) -> Vec<Plugin> {
             Command::Token(Token::RightParent),
             Command::Token(Token::Eq),
             Command::Token(Token::Neq),
+            Command::Token(Token::And),
+            Command::Token(Token::Or),
+            Command::Token(Token::Not),
             Command::Token(Token::Comma),
             Command::Comment("".to_owned()),
         ],
@@ -25,6 +25,23 @@ impl<T: PartialEq> PartialEq for Spanned<T> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssignmentOperator {
+    Addition,
+    Subtraction,
+    Multiply,
+    Division,
+}
+
+/// Scope that a `local` or `global` assignment writes to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VariableScope {
+    /// Innermost scope, shadows variables of the same name
+    Local,
+    /// Outermost scope of the program
+    Global,
+}
+
 // In TurlicoScript everything is an expression.
 // Some expressions can be used as statements
 // This means that their value does not have to be used
@@ -38,18 +55,40 @@ pub enum Expression {
         expr: Box<Spanned<Expression>>,
         value: Box<Spanned<Expression>>,
     },
+    /// `+=`, `-=`, `*=` and `/=`
+    CompoundAssignment {
+        expr: Box<Spanned<Expression>>,
+        op: AssignmentOperator,
+        value: Box<Spanned<Expression>>,
+    },
+    /// `local $x = value` and `global $x = value`
+    ScopedAssignment {
+        scope: VariableScope,
+        name: String,
+        value: Box<Spanned<Expression>>,
+    },
     ObjDef {
         object: Vec<(Spanned<Expression>, Spanned<Expression>)>
     },
+    ListDef {
+        items: Vec<Spanned<Expression>>
+    },
+    Index {
+        expr: Box<Spanned<Expression>>,
+        index: Box<Spanned<Expression>>,
+    },
     Return {
         value: Box<Spanned<Expression>>,
     },
     Break,
+    Continue,
 
     // Loops
     If {
         cond: Box<Spanned<Expression>>,
         body: Box<Spanned<Expression>>,
+        /// `else` branch, another `If` when chained as `else if`
+        else_body: Option<Box<Spanned<Expression>>>,
     },
     LoopFinite {
         iters: Box<Spanned<Expression>>,
@@ -65,6 +104,12 @@ pub enum Expression {
         step: Option<Box<Spanned<Expression>>>,
         body: Box<Spanned<Expression>>,
     },
+    /// Iterates over items of a list, keys of an object or characters of a string
+    ForEach {
+        var: String,
+        collection: Box<Spanned<Expression>>,
+        body: Box<Spanned<Expression>>,
+    },
     While {
         cond: Box<Spanned<Expression>>,
         body: Box<Spanned<Expression>>,
@@ -72,7 +117,8 @@ pub enum Expression {
 
     // Structure
     FnDef {
-        name: String,
+        /// None for anonymous functions
+        name: Option<String>,
         args: Vec<String>,
         body: Box<Spanned<Expression>>,
     },
@@ -81,6 +127,9 @@ pub enum Expression {
     Negation(Box<Spanned<Expression>>),
     Multiply(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
     Division(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
+    Modulo(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
+    IntDivision(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
+    Power(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
     Addition(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
     Subtraction(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
     Eq(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
@@ -89,11 +138,17 @@ pub enum Expression {
     Gt(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
     Lte(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
     Gte(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
+    Not(Box<Spanned<Expression>>),
+    And(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
+    Or(Box<Spanned<Expression>>, Box<Spanned<Expression>>),
 
     // Literals
     Int(i32),
     Float(f64),
+    Bool(bool),
     String(String),
+    /// String literal with embedded expressions, the parts are joined at runtime
+    Interpolation(Vec<Spanned<Expression>>),
     Image(String),
     Tilemap(String),
     Key(String),
@@ -104,4 +159,133 @@ pub enum Expression {
     None,
 
     Block(Vec<Spanned<Expression>>),
-}
\ No newline at end of file
+}
+impl Expression {
+    /// Direct subexpressions, including bodies of functions
+    pub fn children(&self) -> Vec<&Spanned<Expression>> {
+        match self {
+            Expression::Call { expr, args } => std::iter::once(&**expr).chain(args).collect(),
+            Expression::Assignment { expr, value }
+            | Expression::CompoundAssignment { expr, value, .. } => vec![expr, value],
+            Expression::ScopedAssignment { value, .. } => vec![value],
+            Expression::ObjDef { object } => object
+                .iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            Expression::ListDef { items } => items.iter().collect(),
+            Expression::Interpolation(items) | Expression::Block(items) => items.iter().collect(),
+            Expression::Index { expr, index } => vec![expr, index],
+            Expression::Return { value } => vec![value],
+            Expression::If {
+                cond,
+                body,
+                else_body,
+            } => [cond, body].into_iter().chain(else_body).map(|expr| &**expr).collect(),
+            Expression::LoopFinite { iters, body } => vec![iters, body],
+            Expression::LoopInfinite { body } => vec![body],
+            Expression::For {
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => [start, end].into_iter().chain(step).chain([body]).map(|expr| &**expr).collect(),
+            Expression::ForEach {
+                collection, body, ..
+            } => vec![collection, body],
+            Expression::While { cond, body } => vec![cond, body],
+            Expression::FnDef { body, .. } => vec![body],
+            Expression::Negation(a) | Expression::Not(a) => vec![a],
+            Expression::Multiply(a, b)
+            | Expression::Division(a, b)
+            | Expression::Modulo(a, b)
+            | Expression::IntDivision(a, b)
+            | Expression::Power(a, b)
+            | Expression::Addition(a, b)
+            | Expression::Subtraction(a, b)
+            | Expression::Eq(a, b)
+            | Expression::Neq(a, b)
+            | Expression::Lt(a, b)
+            | Expression::Gt(a, b)
+            | Expression::Lte(a, b)
+            | Expression::Gte(a, b)
+            | Expression::And(a, b)
+            | Expression::Or(a, b) => vec![a, b],
+            Expression::Variable { parent, .. } => parent.iter().map(|expr| &**expr).collect(),
+            Expression::Break
+            | Expression::Continue
+            | Expression::Int(_)
+            | Expression::Float(_)
+            | Expression::Bool(_)
+            | Expression::String(_)
+            | Expression::Image(_)
+            | Expression::Tilemap(_)
+            | Expression::Key(_)
+            | Expression::None => vec![],
+        }
+    }
+
+    /// Mutable version of [`Expression::children`]
+    pub fn children_mut(&mut self) -> Vec<&mut Spanned<Expression>> {
+        match self {
+            Expression::Call { expr, args } => std::iter::once(&mut **expr).chain(args).collect(),
+            Expression::Assignment { expr, value }
+            | Expression::CompoundAssignment { expr, value, .. } => vec![expr, value],
+            Expression::ScopedAssignment { value, .. } => vec![value],
+            Expression::ObjDef { object } => object
+                .iter_mut()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            Expression::ListDef { items } => items.iter_mut().collect(),
+            Expression::Interpolation(items) | Expression::Block(items) => items.iter_mut().collect(),
+            Expression::Index { expr, index } => vec![expr, index],
+            Expression::Return { value } => vec![value],
+            Expression::If {
+                cond,
+                body,
+                else_body,
+            } => [cond, body].into_iter().chain(else_body).map(|expr| &mut **expr).collect(),
+            Expression::LoopFinite { iters, body } => vec![iters, body],
+            Expression::LoopInfinite { body } => vec![body],
+            Expression::For {
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => [start, end].into_iter().chain(step).chain([body]).map(|expr| &mut **expr).collect(),
+            Expression::ForEach {
+                collection, body, ..
+            } => vec![collection, body],
+            Expression::While { cond, body } => vec![cond, body],
+            Expression::FnDef { body, .. } => vec![body],
+            Expression::Negation(a) | Expression::Not(a) => vec![a],
+            Expression::Multiply(a, b)
+            | Expression::Division(a, b)
+            | Expression::Modulo(a, b)
+            | Expression::IntDivision(a, b)
+            | Expression::Power(a, b)
+            | Expression::Addition(a, b)
+            | Expression::Subtraction(a, b)
+            | Expression::Eq(a, b)
+            | Expression::Neq(a, b)
+            | Expression::Lt(a, b)
+            | Expression::Gt(a, b)
+            | Expression::Lte(a, b)
+            | Expression::Gte(a, b)
+            | Expression::And(a, b)
+            | Expression::Or(a, b) => vec![a, b],
+            Expression::Variable { parent, .. } => parent.iter_mut().map(|expr| &mut **expr).collect(),
+            Expression::Break
+            | Expression::Continue
+            | Expression::Int(_)
+            | Expression::Float(_)
+            | Expression::Bool(_)
+            | Expression::String(_)
+            | Expression::Image(_)
+            | Expression::Tilemap(_)
+            | Expression::Key(_)
+            | Expression::None => vec![],
+        }
+    }
+}
@@ -0,0 +1,774 @@
+//! Compiler of the syntax tree to bytecode that runs on [`crate::vm`].
+//!
+//! Variables that a function can create get slots in its scope.
+//! Names that are not assigned yet are resolved at runtime in the same way
+//! as in the tree walker: the enclosing functions are searched first and then the global scope.
+//! Global variables of the program are slots too, they are loaded from [`crate::interpreter::Context::stack`].
+//! Their slots are shared by all programs of a context, so functions keep working after the program
+//! that defined them ended.
+
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use crate::{
+    ast::{Expression, Spanned, VariableScope},
+    interpreter::MathOperator,
+    value::Value,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Instruction {
+    /// Pushes a constant of the function
+    Constant(usize),
+    Pop,
+    /// Reads a global variable, property variables are called
+    LoadGlobal(usize),
+    /// Assigns a global variable and pushes its previous value
+    StoreGlobal(usize),
+    /// Reads a [`Variable`] of the function
+    LoadVariable(usize),
+    /// Assigns a [`Variable`] in the nearest scope that has it,
+    /// a new one is created in the scope of the function
+    StoreVariable(usize),
+    /// Creates or assigns a variable in the scope of the function and pushes its previous value
+    StoreSlot(usize),
+    /// Reads a field of the object on the stack, the name is a constant
+    LoadMember(usize),
+    /// Fails if the value on the stack is neither a list nor an object
+    CheckIndexable,
+    /// Turns the object on the stack into a place of its named field
+    PlaceMember(usize),
+    /// Turns the list or object and the index on the stack into a place
+    PlaceIndex,
+    DupPlace,
+    LoadPlace,
+    /// Writes the value on the stack to the last place and pushes its previous value
+    StorePlace,
+    /// Fails if the value at the given depth below the top of the stack is not an int
+    CheckInt(usize, IntCheck),
+    /// Fails if the value on the stack cannot be a key of an object
+    CheckKey,
+    MakeList(usize),
+    /// Collects the given number of key and value pairs
+    MakeObject(usize),
+    Interpolate(usize),
+    /// Creates a closure of a nested function over the scope of the running function
+    MakeClosure(usize),
+    /// Checks the callee before its arguments are evaluated
+    PrepareCall(usize),
+    Call(usize),
+    Return,
+    Negate,
+    Not,
+    /// Fails if the operand of a logical operator on the stack is not a bool
+    CheckBool,
+    /// Binary operator with spans of the operands
+    Binary(MathOperator, usize),
+    Jump(usize),
+    /// Pops the condition and jumps unless it is true
+    JumpUnlessTrue(usize),
+    /// Jumps if the value on the stack is false, the value stays on the stack
+    JumpIfFalse(usize),
+    /// Jumps if the value on the stack is true, the value stays on the stack
+    JumpIfTrue(usize),
+    /// Starts a loop whose state is the given number of values on the stack.
+    /// `continue` jumps to `next`, `break` jumps to `end`.
+    EnterLoop {
+        state: usize,
+        next: usize,
+        end: usize,
+    },
+    /// Drops the state of the finished loop and pushes its value
+    ExitLoop,
+    Break,
+    Continue,
+    /// Decrements the remaining count of `loop n` or jumps when it is zero
+    LoopCount(usize),
+    /// Pushes the counter of a `for` loop or jumps when it reaches the end
+    ForNext(usize),
+    /// Adds the step to the counter of a `for` loop, jumps when it overflows
+    ForStep(usize),
+    /// Replaces the collection of a `for each` loop with its items and a position
+    IntoItems,
+    /// Pushes the next item of a `for each` loop or jumps when there are no more
+    ForEachNext(usize),
+    Fail(Failure),
+}
+
+/// Value that has to be an int
+#[derive(Debug, Clone, Copy)]
+pub enum IntCheck {
+    IterationCount,
+    ForStart,
+    ForEnd,
+    ForStep,
+}
+
+/// Errors that are known at compile time but reported only when reached
+#[derive(Debug, Clone, Copy)]
+pub enum Failure {
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    NotAssignable,
+}
+
+/// Name used by a function that may be defined in several scopes
+#[derive(Debug)]
+pub struct Variable {
+    pub name: String,
+    /// Slot in the scope of the function
+    pub slot: Option<usize>,
+    /// Slots of the enclosing functions as the number of scopes up and the slot, the nearest first
+    pub outer: Vec<(usize, usize)>,
+    pub global: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: Option<String>,
+    /// Number of parameters, they are the first slots
+    pub params: usize,
+    /// Names of the variables in the scope of the function
+    pub slots: Vec<String>,
+    pub code: Vec<Instruction>,
+    /// Source span of every instruction for errors
+    pub spans: Vec<Range<usize>>,
+    /// Spans of both operands of binary operators
+    pub operand_spans: Vec<(Range<usize>, Range<usize>)>,
+    pub constants: Vec<Value>,
+    pub variables: Vec<Variable>,
+    /// Functions defined in the body
+    pub functions: Vec<Rc<Function>>,
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub main: Rc<Function>,
+}
+
+/// Slots of the global variables of a context, new programs only add slots
+#[derive(Debug, Default)]
+pub struct Globals {
+    /// Names of the global variables by their slots
+    pub names: Vec<String>,
+    slots: HashMap<String, usize>,
+}
+
+impl Globals {
+    fn slot(&mut self, name: &str) -> usize {
+        match self.slots.get(name) {
+            Some(slot) => *slot,
+            None => {
+                self.names.push(name.to_owned());
+                self.slots.insert(name.to_owned(), self.names.len() - 1);
+                self.names.len() - 1
+            }
+        }
+    }
+}
+
+/// Compiles the program, its global variables get slots in `globals`
+pub fn compile(program: &Spanned<Expression>, globals: &mut Globals) -> Program {
+    let mut compiler = Compiler {
+        globals,
+        functions: vec![],
+    };
+    let main = compiler.function(None, &[], program);
+    Program {
+        main: Rc::new(main),
+    }
+}
+
+/// Function that is being compiled
+struct Builder {
+    function: Function,
+    slots: HashMap<String, usize>,
+    /// Number of loops around the current instruction
+    loops: usize,
+    body_span: Range<usize>,
+}
+
+/// Where a name is read from or written to
+enum Access {
+    Global(usize),
+    Variable(usize),
+}
+
+struct Compiler<'a> {
+    globals: &'a mut Globals,
+    /// Functions that are being compiled, the first one is the top level of the program
+    functions: Vec<Builder>,
+}
+
+impl Compiler<'_> {
+    fn function(
+        &mut self,
+        name: Option<String>,
+        params: &[String],
+        body: &Spanned<Expression>,
+    ) -> Function {
+        let mut function = Function {
+            name,
+            params: params.len(),
+            ..Default::default()
+        };
+        let mut slots = HashMap::new();
+        // The scope of the top level is the global one
+        if !self.functions.is_empty() {
+            function.slots.extend(params.iter().cloned());
+            collect_slots(body, &mut function.slots);
+            for (slot, name) in function.slots.iter().enumerate() {
+                // A repeated parameter gets the last argument
+                if slot < params.len() || !slots.contains_key(name) {
+                    slots.insert(name.to_owned(), slot);
+                }
+            }
+        }
+        self.functions.push(Builder {
+            function,
+            slots,
+            loops: 0,
+            body_span: body.span.to_owned(),
+        });
+        self.expression(body);
+        self.emit(Instruction::Return, body.span.to_owned());
+        self.functions.pop().unwrap().function
+    }
+
+    fn builder(&mut self) -> &mut Builder {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Range<usize>) -> usize {
+        let function = &mut self.builder().function;
+        function.code.push(instruction);
+        function.spans.push(span);
+        function.code.len() - 1
+    }
+
+    /// Position of the next instruction
+    fn here(&mut self) -> usize {
+        self.builder().function.code.len()
+    }
+
+    /// Sets the target of a jump
+    fn patch(&mut self, at: usize, target: usize) {
+        match &mut self.builder().function.code[at] {
+            Instruction::Jump(to)
+            | Instruction::JumpUnlessTrue(to)
+            | Instruction::JumpIfFalse(to)
+            | Instruction::JumpIfTrue(to)
+            | Instruction::LoopCount(to)
+            | Instruction::ForNext(to)
+            | Instruction::ForStep(to)
+            | Instruction::ForEachNext(to) => *to = target,
+            instruction => unreachable!("{:?} is not a jump", instruction),
+        }
+    }
+
+    fn patch_loop(&mut self, at: usize, next_at: usize, end_at: usize) {
+        if let Instruction::EnterLoop { next, end, .. } = &mut self.builder().function.code[at] {
+            *next = next_at;
+            *end = end_at;
+        }
+    }
+
+    fn constant(&mut self, value: Value, span: Range<usize>) {
+        let constants = &mut self.builder().function.constants;
+        constants.push(value);
+        let index = constants.len() - 1;
+        self.emit(Instruction::Constant(index), span);
+    }
+
+    fn global(&mut self, name: &str) -> usize {
+        self.globals.slot(name)
+    }
+
+    fn access(&mut self, name: &str) -> Access {
+        let global = self.global(name);
+        let current = self.functions.len() - 1;
+        let slot = match current {
+            0 => None,
+            _ => self.functions[current].slots.get(name).copied(),
+        };
+        let outer: Vec<(usize, usize)> = (1..current)
+            .rev()
+            .filter_map(|i| {
+                let slot = self.functions[i].slots.get(name)?;
+                Some((current - i, *slot))
+            })
+            .collect();
+        if slot.is_none() && outer.is_empty() {
+            return Access::Global(global);
+        }
+        let variables = &mut self.builder().function.variables;
+        let index = match variables.iter().position(|var| var.name == name) {
+            Some(index) => index,
+            None => {
+                variables.push(Variable {
+                    name: name.to_owned(),
+                    slot,
+                    outer,
+                    global,
+                });
+                variables.len() - 1
+            }
+        };
+        Access::Variable(index)
+    }
+
+    fn load_variable(&mut self, name: &str, span: Range<usize>) {
+        let instruction = match self.access(name) {
+            Access::Global(global) => Instruction::LoadGlobal(global),
+            Access::Variable(index) => Instruction::LoadVariable(index),
+        };
+        self.emit(instruction, span);
+    }
+
+    fn store_variable(&mut self, name: &str, span: Range<usize>) {
+        let instruction = match self.access(name) {
+            Access::Global(global) => Instruction::StoreGlobal(global),
+            Access::Variable(index) => Instruction::StoreVariable(index),
+        };
+        self.emit(instruction, span);
+    }
+
+    /// Stores into the innermost scope like `local`
+    fn store_local(&mut self, name: &str, span: Range<usize>) {
+        let instruction = match self.functions.len() {
+            1 => Instruction::StoreGlobal(self.global(name)),
+            _ => Instruction::StoreSlot(self.builder().slots[name]),
+        };
+        self.emit(instruction, span);
+    }
+
+    fn binary(
+        &mut self,
+        a: &Spanned<Expression>,
+        b: &Spanned<Expression>,
+        op: MathOperator,
+        span: Range<usize>,
+    ) {
+        self.expression(a);
+        self.expression(b);
+        self.operator(op, a.span.to_owned(), b.span.to_owned(), span);
+    }
+
+    fn operator(
+        &mut self,
+        op: MathOperator,
+        span_a: Range<usize>,
+        span_b: Range<usize>,
+        span: Range<usize>,
+    ) {
+        let operand_spans = &mut self.builder().function.operand_spans;
+        operand_spans.push((span_a, span_b));
+        let index = operand_spans.len() - 1;
+        self.emit(Instruction::Binary(op, index), span);
+    }
+
+    /// Compiles a loop body that leaves no value on the stack
+    fn loop_body(&mut self, body: &Spanned<Expression>) {
+        self.builder().loops += 1;
+        self.expression(body);
+        self.builder().loops -= 1;
+        self.emit(Instruction::Pop, body.span.to_owned());
+    }
+
+    fn enter_loop(&mut self, state: usize, span: Range<usize>) -> usize {
+        self.emit(
+            Instruction::EnterLoop {
+                state,
+                next: 0,
+                end: 0,
+            },
+            span,
+        )
+    }
+
+    /// Evaluates a list or an object and an index into a place
+    fn index_place(&mut self, expr: &Spanned<Expression>, index: &Spanned<Expression>) {
+        self.expression(expr);
+        self.emit(Instruction::CheckIndexable, expr.span.to_owned());
+        self.expression(index);
+        self.emit(Instruction::PlaceIndex, index.span.to_owned());
+    }
+
+    /// Compiles an expression that pushes exactly one value
+    fn expression(&mut self, expression: &Spanned<Expression>) {
+        let span = expression.span.to_owned();
+        match &expression.item {
+            Expression::Block(block) => {
+                if block.is_empty() {
+                    self.constant(Value::None, span.to_owned());
+                }
+                for (i, expr) in block.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Instruction::Pop, span.to_owned());
+                    }
+                    self.expression(expr);
+                }
+            }
+            Expression::Call { expr, args } => {
+                self.expression(expr);
+                self.emit(Instruction::PrepareCall(args.len()), span.to_owned());
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.emit(Instruction::Call(args.len()), span);
+            }
+            // Control flow
+            Expression::If {
+                cond,
+                body,
+                else_body,
+            } => {
+                self.expression(cond);
+                let to_else = self.emit(Instruction::JumpUnlessTrue(0), span.to_owned());
+                self.expression(body);
+                let to_end = self.emit(Instruction::Jump(0), span.to_owned());
+                let else_at = self.here();
+                self.patch(to_else, else_at);
+                match else_body {
+                    Some(else_body) => self.expression(else_body),
+                    None => self.constant(Value::None, span),
+                }
+                let end_at = self.here();
+                self.patch(to_end, end_at);
+            }
+            // Loops
+            Expression::LoopInfinite { body } => {
+                let enter = self.enter_loop(0, span.to_owned());
+                let next = self.here();
+                self.loop_body(body);
+                self.emit(Instruction::Jump(next), span);
+                let end = self.here();
+                self.patch_loop(enter, next, end);
+            }
+            Expression::LoopFinite { iters, body } => {
+                self.expression(iters);
+                self.emit(
+                    Instruction::CheckInt(0, IntCheck::IterationCount),
+                    iters.span.to_owned(),
+                );
+                let enter = self.enter_loop(1, span.to_owned());
+                let next = self.here();
+                let to_exit = self.emit(Instruction::LoopCount(0), span.to_owned());
+                self.loop_body(body);
+                self.emit(Instruction::Jump(next), span.to_owned());
+                let exit = self.emit(Instruction::ExitLoop, span);
+                self.patch(to_exit, exit);
+                self.patch_loop(enter, next, exit + 1);
+            }
+            Expression::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                self.expression(start);
+                self.expression(end);
+                match step {
+                    Some(step) => self.expression(step),
+                    None => self.constant(Value::Int(1), span.to_owned()),
+                }
+                self.emit(
+                    Instruction::CheckInt(2, IntCheck::ForStart),
+                    start.span.to_owned(),
+                );
+                self.emit(
+                    Instruction::CheckInt(1, IntCheck::ForEnd),
+                    end.span.to_owned(),
+                );
+                if let Some(step) = step {
+                    self.emit(
+                        Instruction::CheckInt(0, IntCheck::ForStep),
+                        step.span.to_owned(),
+                    );
+                }
+                let enter = self.enter_loop(3, span.to_owned());
+                let head = self.emit(Instruction::ForNext(0), span.to_owned());
+                self.store_local(var, span.to_owned());
+                self.emit(Instruction::Pop, span.to_owned());
+                self.loop_body(body);
+                // Overflow means that the end was passed
+                let next = self.emit(Instruction::ForStep(0), span.to_owned());
+                self.emit(Instruction::Jump(head), span.to_owned());
+                let exit = self.emit(Instruction::ExitLoop, span);
+                self.patch(head, exit);
+                self.patch(next, exit);
+                self.patch_loop(enter, next, exit + 1);
+            }
+            Expression::ForEach {
+                var,
+                collection,
+                body,
+            } => {
+                self.expression(collection);
+                self.emit(Instruction::IntoItems, collection.span.to_owned());
+                let enter = self.enter_loop(2, span.to_owned());
+                let next = self.emit(Instruction::ForEachNext(0), span.to_owned());
+                self.store_local(var, span.to_owned());
+                self.emit(Instruction::Pop, span.to_owned());
+                self.loop_body(body);
+                self.emit(Instruction::Jump(next), span.to_owned());
+                let exit = self.emit(Instruction::ExitLoop, span);
+                self.patch(next, exit);
+                self.patch_loop(enter, next, exit + 1);
+            }
+            Expression::While { cond, body } => {
+                let enter = self.enter_loop(0, span.to_owned());
+                let next = self.here();
+                self.expression(cond);
+                let to_exit = self.emit(Instruction::JumpUnlessTrue(0), span.to_owned());
+                self.loop_body(body);
+                self.emit(Instruction::Jump(next), span.to_owned());
+                let exit = self.emit(Instruction::ExitLoop, span);
+                self.patch(to_exit, exit);
+                self.patch_loop(enter, next, exit + 1);
+            }
+            // Structure
+            Expression::FnDef { name, args, body } => {
+                let function = self.function(name.to_owned(), args, body);
+                let functions = &mut self.builder().function.functions;
+                functions.push(Rc::new(function));
+                let index = functions.len() - 1;
+                self.emit(Instruction::MakeClosure(index), span.to_owned());
+                if let Some(name) = name {
+                    self.store_local(name, span.to_owned());
+                    self.emit(Instruction::Pop, span.to_owned());
+                    self.constant(Value::None, span);
+                }
+            }
+
+            // Keywords
+            Expression::Assignment { expr, value } => match &expr.item {
+                Expression::Variable { parent: None, name } => {
+                    self.expression(value);
+                    self.store_variable(name, span);
+                }
+                Expression::Variable {
+                    parent: Some(parent),
+                    name,
+                } => {
+                    self.expression(value);
+                    self.expression(parent);
+                    let name = self.name(name);
+                    self.emit(Instruction::PlaceMember(name), expr.span.to_owned());
+                    self.emit(Instruction::StorePlace, span);
+                }
+                Expression::Index { expr: list, index } => {
+                    self.expression(value);
+                    self.index_place(list, index);
+                    self.emit(Instruction::StorePlace, span);
+                }
+                _ => {
+                    self.expression(value);
+                    self.emit(
+                        Instruction::Fail(Failure::NotAssignable),
+                        expr.span.to_owned(),
+                    );
+                }
+            },
+            Expression::CompoundAssignment { expr, op, value } => {
+                let operand_span = expr.span.start..value.span.end;
+                match &expr.item {
+                    Expression::Variable { parent: None, name } => {
+                        self.load_variable(name, expr.span.to_owned());
+                        self.expression(value);
+                        self.operator(
+                            (*op).into(),
+                            expr.span.to_owned(),
+                            value.span.to_owned(),
+                            operand_span,
+                        );
+                        self.store_variable(name, span);
+                        return;
+                    }
+                    Expression::Variable {
+                        parent: Some(parent),
+                        name,
+                    } => {
+                        self.expression(parent);
+                        let name = self.name(name);
+                        self.emit(Instruction::PlaceMember(name), expr.span.to_owned());
+                    }
+                    Expression::Index { expr: list, index } => self.index_place(list, index),
+                    _ => {
+                        self.emit(
+                            Instruction::Fail(Failure::NotAssignable),
+                            expr.span.to_owned(),
+                        );
+                        return;
+                    }
+                }
+                // The target is evaluated only once, e.g. in $list[next()] += 1
+                self.emit(Instruction::DupPlace, expr.span.to_owned());
+                self.emit(Instruction::LoadPlace, expr.span.to_owned());
+                self.expression(value);
+                self.operator(
+                    (*op).into(),
+                    expr.span.to_owned(),
+                    value.span.to_owned(),
+                    operand_span,
+                );
+                self.emit(Instruction::StorePlace, span);
+            }
+            Expression::ScopedAssignment { scope, name, value } => {
+                self.expression(value);
+                match scope {
+                    VariableScope::Local => self.store_local(name, span),
+                    VariableScope::Global => {
+                        let global = self.global(name);
+                        self.emit(Instruction::StoreGlobal(global), span);
+                    }
+                }
+            }
+            Expression::Return { value } => {
+                self.expression(value);
+                self.emit(Instruction::Return, span);
+            }
+            Expression::Break | Expression::Continue => {
+                let in_loop = self.builder().loops > 0;
+                let is_break = matches!(expression.item, Expression::Break);
+                let (instruction, span) = match (in_loop, is_break) {
+                    (true, true) => (Instruction::Break, span),
+                    (true, false) => (Instruction::Continue, span),
+                    // Reported for the whole function like in the tree walker
+                    (false, true) => (
+                        Instruction::Fail(Failure::BreakOutsideLoop),
+                        self.builder().body_span.to_owned(),
+                    ),
+                    (false, false) => (
+                        Instruction::Fail(Failure::ContinueOutsideLoop),
+                        self.builder().body_span.to_owned(),
+                    ),
+                };
+                self.emit(instruction, span);
+            }
+            // Operators
+            Expression::Negation(a) => {
+                self.expression(a);
+                self.emit(Instruction::Negate, span);
+            }
+            Expression::Addition(a, b) => self.binary(a, b, MathOperator::Addition, span),
+            Expression::Subtraction(a, b) => self.binary(a, b, MathOperator::Subtraction, span),
+            Expression::Multiply(a, b) => self.binary(a, b, MathOperator::Multiply, span),
+            Expression::Division(a, b) => self.binary(a, b, MathOperator::Division, span),
+            Expression::Modulo(a, b) => self.binary(a, b, MathOperator::Modulo, span),
+            Expression::IntDivision(a, b) => self.binary(a, b, MathOperator::IntDivision, span),
+            Expression::Power(a, b) => self.binary(a, b, MathOperator::Power, span),
+            Expression::Eq(a, b) => self.binary(a, b, MathOperator::Eq, span),
+            Expression::Neq(a, b) => self.binary(a, b, MathOperator::Neq, span),
+            Expression::Lt(a, b) => self.binary(a, b, MathOperator::Lt, span),
+            Expression::Gt(a, b) => self.binary(a, b, MathOperator::Gt, span),
+            Expression::Lte(a, b) => self.binary(a, b, MathOperator::Lte, span),
+            Expression::Gte(a, b) => self.binary(a, b, MathOperator::Gte, span),
+            Expression::Not(a) => {
+                self.expression(a);
+                self.emit(Instruction::Not, a.span.to_owned());
+            }
+            // The right operand is evaluated only when it affects the result
+            Expression::And(a, b) | Expression::Or(a, b) => {
+                self.expression(a);
+                self.emit(Instruction::CheckBool, a.span.to_owned());
+                let jump = match expression.item {
+                    Expression::And(..) => Instruction::JumpIfFalse(0),
+                    _ => Instruction::JumpIfTrue(0),
+                };
+                let to_end = self.emit(jump, span.to_owned());
+                self.emit(Instruction::Pop, span);
+                self.expression(b);
+                self.emit(Instruction::CheckBool, b.span.to_owned());
+                let end = self.here();
+                self.patch(to_end, end);
+            }
+            // Literals
+            Expression::Int(val) => self.constant(Value::Int(*val), span),
+            Expression::Float(val) => self.constant(Value::Float(*val), span),
+            Expression::Bool(val) => self.constant(Value::Bool(*val), span),
+            Expression::None => self.constant(Value::None, span),
+            Expression::String(val) => self.constant(Value::String(val.to_owned()), span),
+            Expression::Image(val) => self.constant(Value::Image(val.to_owned()), span),
+            Expression::Tilemap(val) => self.constant(Value::Tilemap(val.to_owned()), span),
+            Expression::Key(val) => self.constant(Value::Key(val.to_owned()), span),
+            Expression::Variable { parent, name } => match parent {
+                Some(parent) => {
+                    self.expression(parent);
+                    let name = self.name(name);
+                    self.emit(Instruction::LoadMember(name), span);
+                }
+                None => self.load_variable(name, span),
+            },
+            Expression::ObjDef { object } => {
+                for (key, value) in object {
+                    self.expression(key);
+                    self.emit(Instruction::CheckKey, key.span.to_owned());
+                    self.expression(value);
+                }
+                self.emit(Instruction::MakeObject(object.len()), span);
+            }
+            Expression::Interpolation(parts) => {
+                for part in parts {
+                    self.expression(part);
+                }
+                self.emit(Instruction::Interpolate(parts.len()), span);
+            }
+            Expression::ListDef { items } => {
+                for item in items {
+                    self.expression(item);
+                }
+                self.emit(Instruction::MakeList(items.len()), span);
+            }
+            Expression::Index { expr, index } => {
+                self.index_place(expr, index);
+                self.emit(Instruction::LoadPlace, index.span.to_owned());
+            }
+        }
+    }
+
+    /// Field names are stored as string constants
+    fn name(&mut self, name: &str) -> usize {
+        let constants = &mut self.builder().function.constants;
+        match constants
+            .iter()
+            .position(|value| matches!(value, Value::String(string) if string == name))
+        {
+            Some(index) => index,
+            None => {
+                constants.push(Value::String(name.to_owned()));
+                constants.len() - 1
+            }
+        }
+    }
+}
+
+/// Collects names that the code of a function can create in its own scope
+fn collect_slots(expression: &Spanned<Expression>, slots: &mut Vec<String>) {
+    let name = match &expression.item {
+        Expression::Assignment { expr, .. } => match &expr.item {
+            Expression::Variable { parent: None, name } => Some(name),
+            _ => None,
+        },
+        Expression::ScopedAssignment {
+            scope: VariableScope::Local,
+            name,
+            ..
+        } => Some(name),
+        Expression::For { var, .. } | Expression::ForEach { var, .. } => Some(var),
+        Expression::FnDef { name, .. } => name.as_ref(),
+        _ => None,
+    };
+    if let Some(name) = name {
+        if !slots.contains(name) {
+            slots.push(name.to_owned());
+        }
+    }
+    // Nested functions have their own scopes
+    if matches!(expression.item, Expression::FnDef { .. }) {
+        return;
+    }
+    for child in expression.item.children() {
+        collect_slots(child, slots);
+    }
+}
@@ -0,0 +1,116 @@
+//! Frees scopes of finished calls that only reference cycles keep alive.
+//!
+//! Functions capture the scope they are defined in, so a function stored in that scope
+//! forms a cycle that reference counting never frees. When a call ends, the shared values
+//! reachable from its scope are walked and their reference counts are compared with the
+//! references found between them. If nothing outside the walked values refers to the scope,
+//! the engine drops its variables, which breaks the cycles.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::value::{Callable, Value};
+
+pub(crate) type NodeId = *const ();
+
+struct Node {
+    strong: usize,
+    /// References from other walked nodes
+    found: usize,
+    children: Vec<NodeId>,
+}
+
+pub(crate) struct Graph {
+    root: NodeId,
+    nodes: HashMap<NodeId, Node>,
+}
+
+impl Graph {
+    /// Starts at the scope of a finished call, the caller holds one reference to it
+    pub fn new<T>(root: &Rc<T>) -> Self {
+        let root_id = Rc::as_ptr(root) as NodeId;
+        let node = Node {
+            strong: Rc::strong_count(root) - 1,
+            found: 0,
+            children: vec![],
+        };
+        Self {
+            root: root_id,
+            nodes: HashMap::from([(root_id, node)]),
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Records a reference from `parent` to `child`, returns its id when it has to be walked
+    pub fn reference<T: ?Sized>(&mut self, parent: NodeId, child: &Rc<T>) -> Option<NodeId> {
+        let id = Rc::as_ptr(child) as *const () as NodeId;
+        self.nodes.get_mut(&parent).unwrap().children.push(id);
+        match self.nodes.entry(id) {
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().found += 1;
+                None
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Node {
+                    strong: Rc::strong_count(child),
+                    found: 1,
+                    children: vec![],
+                });
+                Some(id)
+            }
+        }
+    }
+
+    /// Walks the shared parts of a value, `callable` walks what functions capture
+    pub fn value(
+        &mut self,
+        parent: NodeId,
+        value: &Value,
+        callable: &mut dyn FnMut(&mut Self, NodeId, &Callable),
+    ) {
+        match value {
+            Value::List(list) => {
+                if let Some(id) = self.reference(parent, list) {
+                    for item in list.borrow().iter() {
+                        self.value(id, item, callable);
+                    }
+                }
+            }
+            Value::Object(object) => {
+                if let Some(id) = self.reference(parent, object) {
+                    for field in object.borrow().fields.values() {
+                        self.value(id, field, callable);
+                    }
+                }
+            }
+            Value::Callable(func) => callable(self, parent, func),
+            Value::EvaluatedReturn(value) => self.value(parent, value, callable),
+            _ => {}
+        }
+    }
+
+    /// Whether something outside the walked values still refers to the root
+    pub fn root_is_alive(&self) -> bool {
+        let mut alive: Vec<NodeId> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.strong > node.found)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut visited = HashSet::new();
+        while let Some(id) = alive.pop() {
+            if id == self.root {
+                return true;
+            }
+            if visited.insert(id) {
+                alive.extend(&self.nodes[&id].children);
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,253 @@
+//! Rendering of errors for users, as plain text, text with ANSI colors or JSON.
+
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::{
+    ast::Spanned,
+    error::{CallFrame, Error},
+    locale::{Language, Message},
+};
+
+/// Longer spans are shortened in text outputs
+const MAX_SNIPPET_LINES: usize = 4;
+
+// https://chrisyeh96.github.io/2020/03/28/terminal-colors.html
+const ANSI_RED: &str = "\x1b[31m";
+const ANSI_BOLD_RED: &str = "\x1b[1;31m";
+const ANSI_BOLD_YELLOW: &str = "\x1b[1;33m";
+const ANSI_YELLOW: &str = "\x1b[33m";
+const ANSI_BLUE: &str = "\x1b[34m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    /// Plain text with colors for terminals
+    Ansi,
+    /// Array of objects with the message and positions
+    Json,
+}
+
+/// Position in the source, both numbers start from one.
+/// Columns are counted in characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Converts byte offsets of a source to lines and columns
+pub struct SourceMap<'a> {
+    source: &'a str,
+    /// Byte offsets of starts of the lines
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+        }
+    }
+
+    pub fn location(&self, offset: usize) -> Location {
+        let offset = self.clamp(offset);
+        let line = self.line_index(offset);
+        let column = self.source[self.line_starts[line]..offset].chars().count() + 1;
+        Location {
+            line: line + 1,
+            column,
+        }
+    }
+
+    /// Index of the line that contains the offset
+    fn line_index(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset) - 1
+    }
+
+    /// Text of the line without the line break
+    fn line(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line];
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.source.len(), |next| next - 1);
+        self.source[start..end].trim_end_matches('\r')
+    }
+
+    /// Moves the offset into the source and to a character boundary
+    fn clamp(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    /// The program can run but probably does not do what was intended
+    Warning,
+}
+
+/// Error with the function calls that led to it
+#[derive(Debug, Clone, Copy)]
+pub struct Diagnostic<'a> {
+    pub error: &'a Spanned<Error>,
+    pub trace: &'a [CallFrame],
+    pub severity: Severity,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(error: &'a Spanned<Error>, trace: &'a [CallFrame]) -> Self {
+        Self {
+            error,
+            trace,
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn with_severity(self, severity: Severity) -> Self {
+        Self { severity, ..self }
+    }
+}
+
+impl<'a> From<&'a Spanned<Error>> for Diagnostic<'a> {
+    fn from(error: &'a Spanned<Error>) -> Self {
+        Self::new(error, &[])
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDiagnostic {
+    severity: Severity,
+    message: String,
+    /// Byte offsets
+    span: Range<usize>,
+    start: Location,
+    /// Position after the last character of the span
+    end: Location,
+    trace: Vec<JsonCallFrame>,
+}
+
+#[derive(Serialize)]
+struct JsonCallFrame {
+    name: String,
+    start: Location,
+}
+
+/// Renders errors of a program. Spans must be byte offsets of the source.
+pub fn render(source: &str, diagnostics: &[Diagnostic], format: Format, lang: Language) -> String {
+    let map = SourceMap::new(source);
+    match format {
+        Format::Json => {
+            let diagnostics = diagnostics
+                .iter()
+                .map(|diagnostic| JsonDiagnostic {
+                    severity: diagnostic.severity,
+                    message: diagnostic.error.item.message(lang),
+                    span: diagnostic.error.span.clone(),
+                    start: map.location(diagnostic.error.span.start),
+                    end: map.location(diagnostic.error.span.end),
+                    trace: diagnostic
+                        .trace
+                        .iter()
+                        .map(|frame| JsonCallFrame {
+                            name: frame.name.to_owned(),
+                            start: map.location(frame.span.start),
+                        })
+                        .collect(),
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_string(&diagnostics).expect("Diagnostics are always serializable")
+        }
+        Format::Plain | Format::Ansi => diagnostics
+            .iter()
+            .map(|diagnostic| render_text(&map, diagnostic, format == Format::Ansi, lang))
+            .collect::<Vec<String>>()
+            .join("\n\n"),
+    }
+}
+
+fn render_text(map: &SourceMap, diagnostic: &Diagnostic, ansi: bool, lang: Language) -> String {
+    let color = |code: &'static str| if ansi { code } else { "" };
+    let reset = color(ANSI_RESET);
+    let span = &diagnostic.error.span;
+    let start = map.clamp(span.start);
+    let end = map.clamp(span.end).max(start);
+    let location = map.location(start);
+    let (label, label_color, marker_color) = match diagnostic.severity {
+        Severity::Error => (Message::Error, ANSI_BOLD_RED, ANSI_RED),
+        Severity::Warning => (Message::Warning, ANSI_BOLD_YELLOW, ANSI_YELLOW),
+    };
+
+    let mut text = format!(
+        "{}{}{}: {}{}{}\n --> {}",
+        color(label_color),
+        label.text(lang),
+        reset,
+        color(ANSI_YELLOW),
+        diagnostic.error.item.message(lang),
+        reset,
+        location_text(location, lang),
+    );
+
+    let first_line = map.line_index(start);
+    let last_line = map.line_index(end);
+    let gutter_width = (last_line + 1).to_string().len();
+    for line in first_line..=last_line.min(first_line + MAX_SNIPPET_LINES - 1) {
+        let line_start = map.line_starts[line];
+        let code = map.line(line);
+        // Part of the line covered by the span, in characters
+        let from = start.saturating_sub(line_start).min(code.len());
+        let to = end.saturating_sub(line_start).min(code.len());
+        let offset = code[..from].chars().count();
+        let width = code[from..to].chars().count().max(1);
+        text += &format!(
+            "\n{}{:>gutter_width$} |{} {}\n{}{:>gutter_width$} |{} {}{}{}{}",
+            color(ANSI_BLUE),
+            line + 1,
+            reset,
+            code,
+            color(ANSI_BLUE),
+            "",
+            reset,
+            " ".repeat(offset),
+            color(marker_color),
+            "^".repeat(width),
+            reset,
+        );
+    }
+    if last_line >= first_line + MAX_SNIPPET_LINES {
+        text += &format!("\n{}{:>gutter_width$} |{} ...", color(ANSI_BLUE), "", reset);
+    }
+
+    if !diagnostic.trace.is_empty() {
+        text.push('\n');
+        text.push_str(Message::CallStack.text(lang));
+        for frame in diagnostic.trace {
+            text += &format!(
+                "\n  {} ({})",
+                frame.name,
+                location_text(map.location(frame.span.start), lang)
+            );
+        }
+    }
+    text
+}
+
+fn location_text(location: Location, lang: Language) -> String {
+    Message::Location.format(
+        lang,
+        &[location.line.to_string(), location.column.to_string()],
+    )
+}
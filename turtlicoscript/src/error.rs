@@ -1,62 +1,215 @@
-use std::fmt::Display;
+use std::{fmt::Display, ops::Range};
 
-use chumsky::prelude::Simple;
-
-use crate::{tokens::Token, ast::Spanned};
+use crate::{
+    ast::Spanned,
+    diagnostics::{self, Diagnostic, Format},
+    locale::{describe_token, Language, Message},
+    tokens::Token,
+};
 
 #[derive(Debug, Clone)]
 pub enum Error {
     InvalidToken,
-    UnexpectedToken(Token),
-    SyntaxError(Simple<Token>),
+    InvalidEscape(String),
+    /// Token that does not fit the syntax, `None` stands for the end of the program
+    UnexpectedToken {
+        found: Option<Token>,
+        /// Tokens that would be accepted, in no particular order
+        expected: Vec<Option<Token>>,
+        /// The innermost construct that was being parsed
+        context: Option<SyntaxContext>,
+    },
+    /// Opening bracket without its closing pair
+    UnclosedDelimiter(Token),
+    UnmatchedBrace,
+    ExpectedOneExpression,
+    /// Number literal that cannot be represented (its text)
+    InvalidNumber(String),
+    /// Function call where only a variable or a field can be
+    ExpectedVariable,
+    /// Other syntax errors with the text of the parser
+    InvalidSyntax(String),
     ThisCannotBeCalled(String),
     ThisIsNotAssignable,
     InvalidIterationCount,
     InvalidForStart,
     InvalidForEnd,
     InvalidForStep,
+    InvalidForCollection,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    /// Maximum call depth was exceeded (names of the innermost calls, the last one first)
+    StackOverflow(Vec<String>),
     RuntimeError(RuntimeError),
-    TypeError(String),
+    TypeError(TypeErrorKind),
     Interrupted,
+    /// [`crate::interpreter::Context::engine`] was changed after the context ran a program
+    EngineChanged,
+    /// Step budget or time limit of the run was exhausted (number of evaluated steps)
+    BudgetExhausted(u64),
+    /// Statements after `return`, `break` or `continue`
+    UnreachableCode,
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+impl Error {
+    /// Text of the error for users of the language
+    pub fn message(&self, lang: Language) -> String {
+        match self {
+            Error::InvalidToken => Message::InvalidToken.text(lang).to_owned(),
+            Error::InvalidEscape(literal) => Message::InvalidEscape.format(lang, &[literal.to_owned()]),
+            Error::UnexpectedToken {
+                found,
+                expected,
+                context,
+            } => unexpected_token_message(found.as_ref(), expected, *context, lang),
+            Error::UnclosedDelimiter(delimiter) => {
+                Message::Unclosed.format(lang, &[describe_token(delimiter, lang)])
+            }
+            Error::UnmatchedBrace => Message::UnmatchedBrace.text(lang).to_owned(),
+            Error::ExpectedOneExpression => Message::ExpectedOneExpression.text(lang).to_owned(),
+            Error::InvalidNumber(literal) => Message::InvalidNumber.format(lang, &[literal.to_owned()]),
+            Error::ExpectedVariable => Message::ExpectedVariable.text(lang).to_owned(),
+            Error::InvalidSyntax(text) => Message::InvalidSyntax.format(lang, &[text.to_owned()]),
+            Error::ThisCannotBeCalled(value) => Message::ThisCannotBeCalled.format(lang, &[value.to_owned()]),
+            Error::ThisIsNotAssignable => Message::ThisIsNotAssignable.text(lang).to_owned(),
+            Error::InvalidIterationCount => Message::InvalidIterationCount.text(lang).to_owned(),
+            Error::InvalidForStart => Message::InvalidForStart.text(lang).to_owned(),
+            Error::InvalidForEnd => Message::InvalidForEnd.text(lang).to_owned(),
+            Error::InvalidForStep => Message::InvalidForStep.text(lang).to_owned(),
+            Error::InvalidForCollection => Message::InvalidForCollection.text(lang).to_owned(),
+            Error::BreakOutsideLoop => Message::BreakOutsideLoop.text(lang).to_owned(),
+            Error::ContinueOutsideLoop => Message::ContinueOutsideLoop.text(lang).to_owned(),
+            Error::StackOverflow(trace) => Message::StackOverflow.format(lang, &[trace.join(" ← ")]),
+            Error::RuntimeError(err) => err.message(lang),
+            Error::TypeError(kind) => kind.message(lang),
+            Error::Interrupted => Message::Interrupted.text(lang).to_owned(),
+            Error::EngineChanged => Message::EngineChanged.text(lang).to_owned(),
+            Error::BudgetExhausted(steps) => Message::BudgetExhausted.format(lang, &[steps.to_string()]),
+            Error::UnreachableCode => Message::UnreachableCode.text(lang).to_owned(),
+        }
     }
 }
 
-impl Spanned<Error> {
-    pub fn build_message(&self, source: &str) -> String {
-        let error_start = source[..self.span.start].rfind('\n').unwrap_or(0);
-        let error_end = source[self.span.end..].find('\n').map_or(source.len(), |i| self.span.end + i);
-
-        let mut i = 0;
-        let mut i_line = 0;
-        let error_line = loop {
-            if let Some(char) = source.chars().nth(i) {
-                if char == '\n' {
-                    i_line += 1;
-                }
-                if i >= self.span.start {
-                    break Some(i_line)
-                }
-            } else {
-                break None
+/// Operation on a value of a wrong type, types are named by [`crate::value::Value::type_to_string`]
+#[derive(Debug, Clone)]
+pub enum TypeErrorKind {
+    /// Operator that does not support its operand (type)
+    Operand(String),
+    /// Operator that does not support this combination of operands (left type, right type)
+    Operands(String, String),
+    /// Index into a value that is not a list or an object (type)
+    NotIndexable(String),
+    /// List index that is not an int (type)
+    ListIndex(String),
+    NotAnObject,
+    PropertyNotCallable,
+}
+
+impl TypeErrorKind {
+    pub fn message(&self, lang: Language) -> String {
+        match self {
+            TypeErrorKind::Operand(value_type) => Message::OperandType.format(lang, &[value_type.to_owned()]),
+            TypeErrorKind::Operands(left, right) => {
+                Message::OperandTypes.format(lang, &[left.to_owned(), right.to_owned()])
             }
-            i += 1;
-        }.unwrap_or(0);
+            TypeErrorKind::NotIndexable(value_type) => Message::NotIndexable.format(lang, &[value_type.to_owned()]),
+            TypeErrorKind::ListIndex(value_type) => Message::ListIndexType.format(lang, &[value_type.to_owned()]),
+            TypeErrorKind::NotAnObject => Message::NotAnObject.text(lang).to_owned(),
+            TypeErrorKind::PropertyNotCallable => Message::PropertyNotCallable.text(lang).to_owned(),
+        }
+    }
+}
+
+/// Syntax errors that expect more tokens only say which token was unexpected
+const MAX_EXPECTED_TOKENS: usize = 4;
+
+/// "Expected X, found Y" for errors of the parser
+fn unexpected_token_message(
+    found: Option<&Token>,
+    expected: &[Option<Token>],
+    context: Option<SyntaxContext>,
+    lang: Language,
+) -> String {
+    let describe = |token: Option<&Token>| match token {
+        Some(token) => describe_token(token, lang),
+        None => Message::EndOfProgram.text(lang).to_owned(),
+    };
+    // The set of expected tokens has no order
+    let mut expected = expected.iter().map(|token| describe(token.as_ref())).collect::<Vec<String>>();
+    expected.sort();
+    expected.dedup();
+    let found = describe(found);
+    let message = match expected.split_last() {
+        Some((last, [])) => Message::ExpectedFound.format(lang, &[last.to_owned(), found]),
+        // Long lists do not help
+        Some((last, rest)) if expected.len() <= MAX_EXPECTED_TOKENS => {
+            let expected = format!("{} {} {}", rest.join(", "), Message::Or.text(lang), last);
+            Message::ExpectedFound.format(lang, &[expected, found])
+        }
+        _ => Message::Unexpected.format(lang, &[found]),
+    };
+    match context {
+        Some(context) => Message::InContext.format(lang, &[message, context.message().text(lang).to_owned()]),
+        None => message,
+    }
+}
+
+/// Construct of the language that a syntax error was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxContext {
+    IfCondition,
+    ForHeader,
+    FunctionArgs,
+}
+
+impl SyntaxContext {
+    const ALL: [SyntaxContext; 3] = [
+        SyntaxContext::IfCondition,
+        SyntaxContext::ForHeader,
+        SyntaxContext::FunctionArgs,
+    ];
+
+    /// Label of the construct in the parser
+    pub fn label(self) -> &'static str {
+        match self {
+            SyntaxContext::IfCondition => "if condition",
+            SyntaxContext::ForHeader => "for header",
+            SyntaxContext::FunctionArgs => "function arguments",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<SyntaxContext> {
+        SyntaxContext::ALL.into_iter().find(|context| context.label() == label)
+    }
 
-        // https://chrisyeh96.github.io/2020/03/28/terminal-colors.html
-        let mut bad_code = source[error_start..error_end].trim_matches('\n').to_owned();
-        println!("{}:{} {}:{}", error_start, error_end, self.span.start, self.span.end);
+    fn message(self) -> Message {
+        match self {
+            SyntaxContext::IfCondition => Message::IfCondition,
+            SyntaxContext::ForHeader => Message::ForHeader,
+            SyntaxContext::FunctionArgs => Message::FunctionArgs,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message(Language::default()))
+    }
+}
 
-        let line_error_start = isize::max(self.span.start as isize - error_start as isize - 1, 0) as usize;
-        let line_error_end = usize::min(self.span.end - error_start - 1, bad_code.len());
+/// Function call that was being evaluated when an error occurred
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    pub name: String,
+    /// Span of the call expression
+    pub span: Range<usize>,
+}
 
-        bad_code.insert_str(line_error_end, "\x1b[0m");
-        bad_code.insert_str(line_error_start, "\x1b[41m");
-        format!("An \x1b[31merror\x1b[0m occurred on line {}:\n{}\n\x1b[33m{}\x1b[0m", error_line + 1, bad_code, self.item)
+impl Spanned<Error> {
+    /// Message for terminals, see [`crate::diagnostics`] for other formats.
+    /// `trace` lists the function calls that led to the error, see [`crate::interpreter::Context::error_trace`]
+    pub fn build_message(&self, source: &str, trace: &[CallFrame]) -> String {
+        diagnostics::render(source, &[Diagnostic::new(self, trace)], Format::Ansi, Language::default())
     }
 }
 
@@ -84,10 +237,51 @@ pub enum RuntimeError {
     InvalidTilemap,
     /// Invalid function call flag (on_sprite, transparent etc.)
     InvalidFlag,
+    /// List index is out of range (index, list length)
+    IndexOutOfRange(i32, usize),
+    EmptyList,
+    /// Integer division or modulo by zero
+    DivisionByZero,
+    /// Result of an integer operation does not fit into an int
+    IntegerOverflow,
+}
+
+impl RuntimeError {
+    /// Text of the error for users of the language
+    pub fn message(&self, lang: Language) -> String {
+        match self {
+            RuntimeError::InvalidArgCount(found, expected) => {
+                Message::InvalidArgCount.format(lang, &[found.to_string(), expected.to_string()])
+            }
+            // Positions are counted from one for users
+            RuntimeError::InvalidArgType(pos) => Message::InvalidArgType.format(lang, &[(pos + 1).to_string()]),
+            RuntimeError::MissingParam(name) => Message::MissingParam.format(lang, &[name.to_owned()]),
+            RuntimeError::ExtraParam(name) => Message::ExtraParam.format(lang, &[name.to_owned()]),
+            RuntimeError::InvalidIdentifier(name) => Message::InvalidIdentifier.format(lang, &[name.to_owned()]),
+            RuntimeError::TypeParseError(text) => Message::TypeParseError.format(lang, &[text.to_owned()]),
+            RuntimeError::TypeParseUnsupported(value_type, target) => {
+                Message::TypeParseUnsupported.format(lang, &[value_type.to_owned(), target.to_owned()])
+            }
+            RuntimeError::TypeHashUnsupported => Message::TypeHashUnsupported.text(lang).to_owned(),
+            RuntimeError::TypeError => Message::WrongType.text(lang).to_owned(),
+            RuntimeError::InvalidBlock(name) => Message::InvalidBlock.format(lang, &[name.to_owned()]),
+            RuntimeError::NativeLibraryError(text) => Message::NativeLibraryError.format(lang, &[text.to_owned()]),
+            RuntimeError::MethodCalledAsFunction => Message::MethodCalledAsFunction.text(lang).to_owned(),
+            RuntimeError::InvalidKey => Message::InvalidKey.text(lang).to_owned(),
+            RuntimeError::InvalidTilemap => Message::InvalidTilemap.text(lang).to_owned(),
+            RuntimeError::InvalidFlag => Message::InvalidFlag.text(lang).to_owned(),
+            RuntimeError::IndexOutOfRange(index, len) => {
+                Message::IndexOutOfRange.format(lang, &[index.to_string(), len.to_string()])
+            }
+            RuntimeError::EmptyList => Message::EmptyList.text(lang).to_owned(),
+            RuntimeError::DivisionByZero => Message::DivisionByZero.text(lang).to_owned(),
+            RuntimeError::IntegerOverflow => Message::IntegerOverflow.text(lang).to_owned(),
+        }
+    }
 }
 
 impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "{}", self.message(Language::default()))
     }
 }
\ No newline at end of file
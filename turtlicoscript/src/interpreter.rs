@@ -1,25 +1,72 @@
 use std::{
+    cell::{RefCell, RefMut},
     collections::{HashMap, HashSet},
     ops::Range,
+    rc::Rc,
     sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
 };
 
-use chumsky::prelude::Simple;
-
 use crate::{
-    ast::{Expression, Spanned},
-    error::{Error, RuntimeError},
-    stdlib,
-    value::{Callable, Library, LibraryContext, TSFunc, TSObject, Value},
+    ast::{AssignmentOperator, Expression, Spanned, VariableScope},
+    bytecode, cycles,
+    error::{CallFrame, Error, RuntimeError, TypeErrorKind},
+    optimizer, resolver, stdlib,
+    value::{
+        list_index, Callable, HashableValue, Library, LibraryContext, ListRef, ObjectFields,
+        ObjectRef, TSFunc, TSObject, Value,
+    },
+    vm,
 };
 
 pub type CancellationToken = Arc<AtomicBool>;
 
-enum MathOperator {
+/// Native stack reserved for one call of a script function. A call of `return f($n - 1) + 1`
+/// measured about 6 KB in the tree walker, the rest is for deeper expressions in the body.
+pub const CALL_STACK_SIZE: usize = 16 * 1024;
+/// Native stack reserved for one nested expression in the tree walker,
+/// measured about 1.6 KB in debug builds
+const EVAL_STACK_SIZE: usize = 2 * 1024;
+/// Call depth that fits into a thread stack of the given size
+pub const fn max_call_depth(stack_size: usize) -> usize {
+    stack_size / CALL_STACK_SIZE
+}
+/// Default value of [`Context::max_call_depth`],
+/// low enough for the default stack size of a spawned thread (2 MB)
+pub const DEFAULT_MAX_CALL_DEPTH: usize = max_call_depth(2 * 1024 * 1024);
+/// Number of calls listed in [`Error::StackOverflow`]
+pub(crate) const STACK_OVERFLOW_TRACE_LEN: usize = 5;
+/// The deadline is checked only once per this many steps
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+/// Limits for sandboxed runs, e.g. automated grading of programs.
+/// Every evaluated expression, or instruction of the bytecode engine, is one step.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    /// Maximum number of steps
+    pub steps: Option<u64>,
+    /// Maximum wall-clock time, measured from [`Context::set_budget`]
+    pub time: Option<Duration>,
+}
+
+/// Storage location that an assignable expression refers to
+#[derive(Clone)]
+pub(crate) enum Place {
+    /// Variable in the nearest scope that defines it
+    Variable(String),
+    List(ListRef, usize),
+    Object(ObjectRef, HashableValue),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MathOperator {
     Addition,
     Subtraction,
     Multiply,
     Division,
+    Modulo,
+    IntDivision,
+    Power,
     Eq,
     Neq,
     Lt,
@@ -28,10 +75,56 @@ enum MathOperator {
     Gte,
 }
 
+impl From<AssignmentOperator> for MathOperator {
+    fn from(op: AssignmentOperator) -> Self {
+        match op {
+            AssignmentOperator::Addition => MathOperator::Addition,
+            AssignmentOperator::Subtraction => MathOperator::Subtraction,
+            AssignmentOperator::Multiply => MathOperator::Multiply,
+            AssignmentOperator::Division => MathOperator::Division,
+        }
+    }
+}
+
+/// How [`Context::eval_root`] runs programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Evaluates the syntax tree directly
+    #[default]
+    TreeWalker,
+    /// Compiles the program with [`crate::bytecode`] and runs it on [`crate::vm`]
+    Bytecode,
+}
+
+/// Scopes are shared between the call stack and functions that captured them
+pub type ScopeRef = Rc<RefCell<Scope>>;
+
 pub struct Context {
-    pub stack: Vec<Scope>,
-    libctx: HashMap<String, Box<dyn LibraryContext>>,
+    /// Scopes visible from the currently evaluated code, the innermost is the last one
+    pub stack: Vec<ScopeRef>,
+    pub(crate) libctx: HashMap<String, Box<dyn LibraryContext>>,
     pub cancellable: Option<CancellationToken>,
+    /// Maximum number of nested function calls,
+    /// nested expressions are limited to the native stack of this many calls
+    pub max_call_depth: usize,
+    /// Functions of one engine cannot be called by the other one,
+    /// so it cannot be changed once the context ran a program
+    pub engine: Engine,
+    /// Engine of the programs that already ran in this context
+    used_engine: Option<Engine>,
+    /// Slots of the global variables of compiled programs
+    pub(crate) globals: bytecode::Globals,
+    /// Simplifies programs with [`crate::optimizer`] before they run, can be disabled for debugging
+    pub optimize: bool,
+    /// Functions that are being evaluated, the innermost is the last one
+    frames: Vec<CallFrame>,
+    /// Number of nested expressions that are being evaluated
+    depth: usize,
+    /// Frames at the place where the last error occurred
+    pub(crate) error_trace: Option<Vec<CallFrame>>,
+    steps: u64,
+    max_steps: Option<u64>,
+    deadline: Option<Instant>,
 }
 
 pub struct Scope {
@@ -51,32 +144,136 @@ impl Scope {
 impl Context {
     pub fn new_parent(cancellable: Option<CancellationToken>) -> Self {
         let mut this = Self {
-            stack: vec![Scope::new()],
+            stack: vec![Rc::new(RefCell::new(Scope::new()))],
             libctx: HashMap::new(),
             cancellable: cancellable,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            engine: Engine::default(),
+            used_engine: None,
+            globals: Default::default(),
+            optimize: true,
+            frames: vec![],
+            depth: 0,
+            error_trace: None,
+            steps: 0,
+            max_steps: None,
+            deadline: None,
         };
         this.import_library(stdlib::init_library(), false);
         this
     }
 
+    /// Limits the rest of the run, the step counter starts again from zero
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.steps = 0;
+        self.max_steps = budget.steps;
+        self.deadline = budget.time.map(|time| Instant::now() + time);
+    }
+
+    /// Number of steps evaluated since the budget was set
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Function calls that led to the error returned by the last [`Context::eval_root`],
+    /// the outermost is the first one
+    pub fn error_trace(&self) -> &[CallFrame] {
+        self.error_trace.as_deref().unwrap_or_default()
+    }
+
+    /// Checks the program without running it, names of imported libraries are known
+    pub fn resolve(&self, program: &Spanned<Expression>) -> Vec<resolver::Problem> {
+        let global = self.stack[0].borrow();
+        resolver::resolve(program, global.vars.keys().map(String::as_str))
+    }
+
     #[inline(always)]
-    fn get_scope(&mut self) -> &mut Scope {
-        return self.stack.last_mut().unwrap();
+    fn get_scope(&self) -> RefMut<'_, Scope> {
+        self.stack.last().unwrap().borrow_mut()
     }
 
     pub fn eval_root(&mut self, expression: &Spanned<Expression>) -> Result<Value, Spanned<Error>> {
+        self.error_trace = None;
+        if *self.used_engine.get_or_insert(self.engine) != self.engine {
+            return Err(Spanned::new(
+                Error::EngineChanged,
+                expression.span.to_owned(),
+            ));
+        }
+        let optimized;
+        let expression = if self.optimize {
+            optimized = optimizer::optimized(expression.clone());
+            &optimized
+        } else {
+            expression
+        };
+        if self.engine == Engine::Bytecode {
+            let program = bytecode::compile(expression, &mut self.globals);
+            return vm::run(self, &program);
+        }
         match self.eval(expression) {
             Ok(Value::EvaluatedReturn(value)) => Ok(*value),
+            Ok(Value::Break) => Err(Spanned::new(
+                Error::BreakOutsideLoop,
+                expression.span.to_owned(),
+            )),
+            Ok(Value::Continue) => Err(Spanned::new(
+                Error::ContinueOutsideLoop,
+                expression.span.to_owned(),
+            )),
             other => other,
         }
     }
 
-    fn eval(&mut self, expression: &Spanned<Expression>) -> Result<Value, Spanned<Error>> {
+    /// Counts one step, fails if the run was cancelled or the budget is exhausted
+    #[inline(always)]
+    pub(crate) fn check_limits(&mut self) -> Result<(), Error> {
         if let Some(cancellable) = &self.cancellable {
             if cancellable.load(std::sync::atomic::Ordering::Relaxed) {
-                return Err(Spanned::new(Error::Interrupted, expression.span.to_owned()));
+                return Err(Error::Interrupted);
             }
         }
+        if self
+            .max_steps
+            .is_some_and(|max_steps| self.steps >= max_steps)
+            || self.deadline.is_some_and(|deadline| {
+                self.steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline
+            })
+        {
+            return Err(Error::BudgetExhausted(self.steps));
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn eval(&mut self, expression: &Spanned<Expression>) -> Result<Value, Spanned<Error>> {
+        // Expressions nested in function bodies use the native stack too,
+        // they share the stack that the call depth limit was derived from
+        if self.depth >= self.max_call_depth * CALL_STACK_SIZE / EVAL_STACK_SIZE {
+            let trace = self
+                .frames
+                .iter()
+                .rev()
+                .map(|frame| frame.name.to_owned())
+                .take(STACK_OVERFLOW_TRACE_LEN)
+                .collect();
+            return Err(Spanned::new(
+                Error::StackOverflow(trace),
+                expression.span.to_owned(),
+            ));
+        }
+        self.depth += 1;
+        let result = self.eval_expression(expression);
+        self.depth -= 1;
+        result
+    }
+
+    fn eval_expression(
+        &mut self,
+        expression: &Spanned<Expression>,
+    ) -> Result<Value, Spanned<Error>> {
+        self.check_limits()
+            .map_err(|err| Spanned::new(err, expression.span.to_owned()))?;
         match &expression.item {
             Expression::Block(block) => {
                 let mut last_result = Value::None;
@@ -86,6 +283,7 @@ impl Context {
                             return Ok(Value::EvaluatedReturn(value));
                         }
                         Value::Break => return Ok(Value::Break),
+                        Value::Continue => return Ok(Value::Continue),
                         value => {
                             last_result = value;
                         }
@@ -103,31 +301,31 @@ impl Context {
                 )),
             },          
             // Control flow
-            Expression::If { cond, body } => {
+            Expression::If {
+                cond,
+                body,
+                else_body,
+            } => {
                 let cond_res = self.eval(&cond)?;
                 if matches!(cond_res, Value::Bool(true)) {
                     self.eval(&body)
+                } else if let Some(else_body) = else_body {
+                    self.eval(else_body)
                 } else {
                     Ok(Value::None)
                 }
             }
             // Loops
             Expression::LoopInfinite { body } => loop {
-                match self.eval(&body)? {
-                    Value::Break => {
-                        break Ok(Value::None);
-                    }
-                    _ => {}
+                if let Some(result) = self.eval_loop_body(body)? {
+                    break Ok(result);
                 }
             },
             Expression::LoopFinite { iters, body } => match self.eval(&iters)? {
                 Value::Int(n) => {
                     for _ in 0..n {
-                        match self.eval(&body)? {
-                            Value::Break => {
-                                break;
-                            }
-                            _ => {}
+                        if let Some(result) = self.eval_loop_body(body)? {
+                            return Ok(result);
                         }
                     }
                     Ok(Value::None)
@@ -157,13 +355,14 @@ impl Context {
                                 let mut i = start;
                                 while i < end {
                                     self.get_scope().vars.insert(var.to_owned(), Value::Int(i));
-                                    match self.eval(&body)? {
-                                        Value::Break => {
-                                            break;
-                                        }
-                                        _ => {}
+                                    if let Some(result) = self.eval_loop_body(body)? {
+                                        return Ok(result);
+                                    }
+                                    // Overflow means that the end was passed
+                                    match i.checked_add(step) {
+                                        Some(next) => i = next,
+                                        None => break,
                                     }
-                                    i += step;
                                 }
                                 Ok(Value::None)
                             }
@@ -177,83 +376,121 @@ impl Context {
                     _ => Err(Spanned::new(Error::InvalidForStart, start.span.to_owned())),
                 }
             }
+            Expression::ForEach {
+                var,
+                collection,
+                body,
+            } => {
+                // Items are collected first so the body can modify the collection
+                let items: Vec<Value> = match self.eval(collection)? {
+                    Value::List(list) => list.borrow().clone(),
+                    Value::Object(object) => object
+                        .borrow()
+                        .fields
+                        .keys()
+                        .cloned()
+                        .map(Value::from)
+                        .collect(),
+                    Value::String(str) => {
+                        str.chars().map(|c| Value::String(c.to_string())).collect()
+                    }
+                    _ => {
+                        return Err(Spanned::new(
+                            Error::InvalidForCollection,
+                            collection.span.to_owned(),
+                        ))
+                    }
+                };
+                for item in items {
+                    self.get_scope().vars.insert(var.to_owned(), item);
+                    if let Some(result) = self.eval_loop_body(body)? {
+                        return Ok(result);
+                    }
+                }
+                Ok(Value::None)
+            }
             Expression::While { cond, body } => {
                 loop {
                     let cond = self.eval(&cond)?;
                     if !matches!(cond, Value::Bool(true)) {
                         break;
                     }
-                    match self.eval(&body)? {
-                        Value::Break => {
-                            break;
-                        }
-                        _ => {}
+                    if let Some(result) = self.eval_loop_body(body)? {
+                        return Ok(result);
                     }
                 }
                 Ok(Value::None)
             }
             // Structure
             Expression::FnDef { name, args, body } => {
-                let func = TSFunc {
+                let func = Value::Callable(Callable::Function(Box::new(TSFunc {
+                    name: name.to_owned(),
                     body: (**body).clone(),
                     args: args.to_owned(),
-                };
-                self.get_scope().vars.insert(
-                    name.to_owned(),
-                    Value::Callable(Callable::Function(Box::new(func))),
-                );
-                Ok(Value::None)
+                    closure: self.stack.clone(),
+                })));
+                match name {
+                    Some(name) => {
+                        self.get_scope().vars.insert(name.to_owned(), func);
+                        Ok(Value::None)
+                    }
+                    None => Ok(func),
+                }
             }
 
             // Keywords
-            Expression::Assignment { expr, value } => match &expr.item {
-                Expression::Variable { parent, name } => {
-                    let value = self.eval(&value)?;
-                    match parent {
-                        Some(parent) => {
-                            let parent = self.eval(parent)?;
-                            match parent {
-                                Value::Object(object) => {
-                                    let mut hashmap = (*object).borrow_mut();
-                                    let oldval = hashmap.fields.insert(
-                                        crate::value::HashableValue::String(name.to_owned()),
-                                        value,
-                                    );
-                                    Ok(oldval.unwrap_or(Value::None))
-                                }
-                                _ => Err(Spanned::new(
-                                    Error::TypeError("This is not an object".to_owned()),
-                                    expr.span.to_owned(),
-                                )),
-                            }
-                        }
-                        None => {
-                            let oldval = self.get_scope().vars.insert(name.to_owned(), value);
-                            Ok(oldval.unwrap_or(Value::None))
-                        }
-                    }
-                }
-                _ => Err(Spanned::new(
-                    Error::ThisIsNotAssignable,
+            Expression::Assignment { expr, value } => {
+                let value = self.eval(value)?;
+                let place = self.eval_place(expr)?;
+                self.write_place(place, value)
+                    .map_err(|err| Spanned::new(err, expression.span.to_owned()))
+            }
+            Expression::CompoundAssignment { expr, op, value } => {
+                // The target is evaluated only once, e.g. in $list[next()] += 1
+                let place = self.eval_place(expr)?;
+                let oldval = self
+                    .read_place(&place)
+                    .map_err(|err| Spanned::new(err, expr.span.to_owned()))?;
+                let operand = self.eval(value)?;
+                let result = apply_math_operator(
+                    &oldval,
+                    &operand,
+                    (*op).into(),
                     expr.span.to_owned(),
-                )),
-            },
+                    value.span.to_owned(),
+                )?;
+                self.write_place(place, result)
+                    .map_err(|err| Spanned::new(err, expression.span.to_owned()))
+            }
+            Expression::ScopedAssignment { scope, name, value } => {
+                let value = self.eval(value)?;
+                let scope = match scope {
+                    VariableScope::Local => self.stack.last(),
+                    VariableScope::Global => self.stack.first(),
+                };
+                let oldval = scope
+                    .unwrap()
+                    .borrow_mut()
+                    .vars
+                    .insert(name.to_owned(), value);
+                Ok(oldval.unwrap_or(Value::None))
+            }
             Expression::Return { value } => self
                 .eval(&value)
                 .map(|val| Value::EvaluatedReturn(Box::new(val))),
             Expression::Break => Ok(Value::Break),
+            Expression::Continue => Ok(Value::Continue),
             // Operators
             Expression::Negation(a) => match &self.eval(&a)? {
-                Value::Int(num) => Ok(Value::Int(-num)),
+                Value::Int(num) => num.checked_neg().map(Value::Int).ok_or_else(|| {
+                    Spanned::new(
+                        Error::RuntimeError(RuntimeError::IntegerOverflow),
+                        expression.span.to_owned(),
+                    )
+                }),
                 Value::Float(num) => Ok(Value::Float(-num)),
                 val => Err(Spanned::new(
-                    Error::TypeError(
-                        format!(
-                            "Incompatible type ({}) for this operator",
-                            val.type_to_string()
-                        )
-                        .to_owned(),
-                    ),
+                    Error::TypeError(TypeErrorKind::Operand(val.type_to_string().to_owned())),
                     expression.span.to_owned(),
                 )),
             },
@@ -261,15 +498,24 @@ impl Context {
             Expression::Subtraction(a, b) => self.math_operator(a, b, MathOperator::Subtraction),
             Expression::Multiply(a, b) => self.math_operator(a, b, MathOperator::Multiply),
             Expression::Division(a, b) => self.math_operator(a, b, MathOperator::Division),
+            Expression::Modulo(a, b) => self.math_operator(a, b, MathOperator::Modulo),
+            Expression::IntDivision(a, b) => self.math_operator(a, b, MathOperator::IntDivision),
+            Expression::Power(a, b) => self.math_operator(a, b, MathOperator::Power),
             Expression::Eq(a, b) => self.math_operator(a, b, MathOperator::Eq),
             Expression::Neq(a, b) => self.math_operator(a, b, MathOperator::Neq),
             Expression::Lt(a, b) => self.math_operator(a, b, MathOperator::Lt),
             Expression::Gt(a, b) => self.math_operator(a, b, MathOperator::Gt),
             Expression::Lte(a, b) => self.math_operator(a, b, MathOperator::Lte),
             Expression::Gte(a, b) => self.math_operator(a, b, MathOperator::Gte),
+            Expression::Not(a) => Ok(Value::Bool(!self.eval_bool(a)?)),
+            // The right operand is evaluated only when it affects the result
+            Expression::And(a, b) => Ok(Value::Bool(self.eval_bool(a)? && self.eval_bool(b)?)),
+            Expression::Or(a, b) => Ok(Value::Bool(self.eval_bool(a)? || self.eval_bool(b)?)),
             // Literals
             Expression::Int(val) => Ok(Value::Int(*val)),
             Expression::Float(val) => Ok(Value::Float(*val)),
+            Expression::Bool(val) => Ok(Value::Bool(*val)),
+            Expression::None => Ok(Value::None),
             Expression::String(val) => Ok(Value::String(val.to_owned())),
             Expression::Image(val) => Ok(Value::Image(val.to_owned())),
             Expression::Tilemap(val) => Ok(Value::Tilemap(val.to_owned())),
@@ -286,7 +532,7 @@ impl Context {
                     .map_err(|err: Error| Spanned::new(err, expression.span.to_owned()))?),
             },
             Expression::ObjDef { object } => {
-                let mut fields = HashMap::new();
+                let mut fields = ObjectFields::new();
                 for item in object {
                     fields.insert(
                         self.eval(&item.0)?
@@ -305,71 +551,200 @@ impl Context {
                     },
                 ))))
             }
-            _ => Err(Spanned::new(
-                Error::SyntaxError(Simple::custom(
-                    expression.span.clone(),
-                    format!(
-                        "Interpreter error: unexpected expression {:?}",
-                        expression.item
-                    ),
+            Expression::Interpolation(parts) => {
+                let mut result = String::new();
+                for part in parts {
+                    result.push_str(&self.eval(part)?.to_string());
+                }
+                Ok(Value::String(result))
+            }
+            Expression::ListDef { items } => {
+                let mut list = vec![];
+                for item in items {
+                    list.push(self.eval(item)?);
+                }
+                Ok(list.into())
+            }
+            Expression::Index { expr, index } => {
+                let place = self.eval_index(expr, index)?;
+                self.read_place(&place)
+                    .map_err(|err| Spanned::new(err, index.span.to_owned()))
+            }
+        }
+    }
+
+    /// Evaluates one iteration of a loop body.
+    /// Returns the result of the whole loop if it should stop (break or return).
+    fn eval_loop_body(
+        &mut self,
+        body: &Spanned<Expression>,
+    ) -> Result<Option<Value>, Spanned<Error>> {
+        match self.eval(body)? {
+            Value::Break => Ok(Some(Value::None)),
+            Value::EvaluatedReturn(value) => Ok(Some(Value::EvaluatedReturn(value))),
+            // Continue just skips the rest of the body
+            _ => Ok(None),
+        }
+    }
+
+    /// Evaluates an operand of a logical operator
+    fn eval_bool(&mut self, expr: &Spanned<Expression>) -> Result<bool, Spanned<Error>> {
+        match self.eval(expr)? {
+            Value::Bool(val) => Ok(val),
+            value => Err(Spanned::new(
+                Error::TypeError(TypeErrorKind::Operand(value.type_to_string().to_owned())),
+                expr.span.to_owned(),
+            )),
+        }
+    }
+
+    /// Evaluates a list and a valid index into it
+    fn eval_index(
+        &mut self,
+        expr: &Spanned<Expression>,
+        index: &Spanned<Expression>,
+    ) -> Result<Place, Spanned<Error>> {
+        let list = match self.eval(expr)? {
+            Value::List(list) => list,
+            Value::Object(object) => {
+                let key = HashableValue::try_from(self.eval(index)?)
+                    .map_err(|err| Spanned::new(Error::RuntimeError(err), index.span.to_owned()))?;
+                return Ok(Place::Object(object, key));
+            }
+            value => {
+                return Err(Spanned::new(
+                    Error::TypeError(TypeErrorKind::NotIndexable(
+                        value.type_to_string().to_owned(),
+                    )),
+                    expr.span.to_owned(),
+                ))
+            }
+        };
+        let i = match self.eval(index)? {
+            Value::Int(i) => list_index(i, list.borrow().len())
+                .map_err(|err| Spanned::new(Error::RuntimeError(err), index.span.to_owned()))?,
+            value => {
+                return Err(Spanned::new(
+                    Error::TypeError(TypeErrorKind::ListIndex(value.type_to_string().to_owned())),
+                    index.span.to_owned(),
+                ))
+            }
+        };
+        Ok(Place::List(list, i))
+    }
+
+    /// Evaluates the target of an assignment
+    fn eval_place(&mut self, expr: &Spanned<Expression>) -> Result<Place, Spanned<Error>> {
+        match &expr.item {
+            Expression::Variable { parent: None, name } => Ok(Place::Variable(name.to_owned())),
+            Expression::Variable {
+                parent: Some(parent),
+                name,
+            } => match self.eval(parent)? {
+                Value::Object(object) => Ok(Place::Object(
+                    object,
+                    HashableValue::String(name.to_owned()),
                 )),
-                expression.span.to_owned(),
+                _ => Err(Spanned::new(
+                    Error::TypeError(TypeErrorKind::NotAnObject),
+                    expr.span.to_owned(),
+                )),
+            },
+            Expression::Index { expr, index } => self.eval_index(expr, index),
+            _ => Err(Spanned::new(
+                Error::ThisIsNotAssignable,
+                expr.span.to_owned(),
             )),
         }
     }
 
+    fn read_place(&mut self, place: &Place) -> Result<Value, Error> {
+        match place {
+            Place::Variable(name) => self.get_var(name, None),
+            Place::List(list, i) => {
+                let list = list.borrow();
+                let i = list_index(*i as i32, list.len()).map_err(Error::RuntimeError)?;
+                Ok(list[i].clone())
+            }
+            Place::Object(object, key) => self.get_field(object, key),
+        }
+    }
+
+    /// Stores the value and returns the previous one.
+    /// List indices are checked again because evaluating the value may have shortened the list.
+    fn write_place(&mut self, place: Place, value: Value) -> Result<Value, Error> {
+        let oldval = match place {
+            Place::Variable(name) => {
+                // Variables of outer scopes are updated instead of being shadowed,
+                // new variables are created in the current scope
+                let scope = self
+                    .stack
+                    .iter()
+                    .rev()
+                    .find(|scope| scope.borrow().vars.contains_key(&name))
+                    .unwrap_or_else(|| self.stack.last().unwrap());
+                scope.borrow_mut().vars.insert(name, value)
+            }
+            Place::List(list, i) => {
+                let mut list = list.borrow_mut();
+                let i = list_index(i as i32, list.len()).map_err(Error::RuntimeError)?;
+                Some(std::mem::replace(&mut list[i], value))
+            }
+            Place::Object(object, key) => object.borrow_mut().fields.insert(key, value),
+        };
+        Ok(oldval.unwrap_or(Value::None))
+    }
+
+    /// Reads a field of an object, property fields are evaluated
+    fn get_field(&mut self, object: &ObjectRef, key: &HashableValue) -> Result<Value, Error> {
+        let (value, is_prop) = {
+            let object = object.borrow();
+            match object.fields.get(key) {
+                Some(value) => (value.clone(), object.fields_props.contains(key)),
+                None => {
+                    return Err(Error::RuntimeError(RuntimeError::InvalidIdentifier(
+                        Value::from(key.clone()).to_string(),
+                    )))
+                }
+            }
+        };
+        match (value, is_prop) {
+            (Value::Callable(callable), true) => self
+                .call_callable(&callable, Range { start: 0, end: 0 }, &vec![])
+                .map_err(|err| err.item),
+            (_, true) => Err(Error::TypeError(TypeErrorKind::PropertyNotCallable)),
+            (value, false) => Ok(value),
+        }
+    }
+
     fn get_var(&mut self, name: &str, parent_obj: Option<Value>) -> Result<Value, Error> {
         match parent_obj {
             Some(parent_obj) => match parent_obj {
                 Value::Object(object) => {
-                    let object = object.borrow();
-                    let field_name = &crate::value::HashableValue::String(name.to_owned());
-                    match object.fields.get(field_name) {
-                        Some(value) => {
-                            if object.fields_props.contains(field_name) {
-                                match value {
-                                    Value::Callable(callable) => self
-                                        .call_callable(
-                                            callable,
-                                            Range { start: 0, end: 0 },
-                                            &vec![],
-                                        )
-                                        .map_err(|err| err.item),
-                                    _ => Err(Error::TypeError(
-                                        "Property field is not a callable".to_owned(),
-                                    )),
-                                }
-                            } else {
-                                Ok(value.clone())
-                            }
-                        }
-                        None => Err(Error::RuntimeError(RuntimeError::InvalidIdentifier(
-                            name.to_owned(),
-                        ))),
-                    }
+                    self.get_field(&object, &HashableValue::String(name.to_owned()))
                 }
-                _ => Err(Error::TypeError("This is not an object".to_owned())),
+                _ => Err(Error::TypeError(TypeErrorKind::NotAnObject)),
             },
             None => {
-                for scope in self.stack.iter_mut().rev() {
-                    if let Some(value) = scope.vars.get(name) {
-                        return if scope.vars_props.contains(name) {
-                            match value.clone() {
-                                Value::Callable(callable) => self
-                                    .call_callable(&callable, Range { start: 0, end: 0 }, &vec![])
-                                    .map_err(|err| err.item),
-                                _ => Err(Error::TypeError(
-                                    "Property field is not a callable".to_owned(),
-                                )),
-                            }
-                        } else {
-                            Ok(value.clone())
-                        };
-                    }
+                let found = self.stack.iter().rev().find_map(|scope| {
+                    let scope = scope.borrow();
+                    scope
+                        .vars
+                        .get(name)
+                        .map(|value| (value.clone(), scope.vars_props.contains(name)))
+                });
+                match found {
+                    Some((value, true)) => match value {
+                        Value::Callable(callable) => self
+                            .call_callable(&callable, Range { start: 0, end: 0 }, &vec![])
+                            .map_err(|err| err.item),
+                        _ => Err(Error::TypeError(TypeErrorKind::PropertyNotCallable)),
+                    },
+                    Some((value, false)) => Ok(value),
+                    None => Err(Error::RuntimeError(RuntimeError::InvalidIdentifier(
+                        name.to_owned(),
+                    ))),
                 }
-                return Err(Error::RuntimeError(RuntimeError::InvalidIdentifier(
-                    name.to_owned(),
-                )));
             }
         }
     }
@@ -411,22 +786,54 @@ impl Context {
                 for (argi, argname) in func.args.iter().enumerate() {
                     args_evaluated.push((argname.to_owned(), self.eval(&args[argi])?));
                 }
+                let name = func.name.as_deref().unwrap_or("<anonymous>").to_owned();
+                if self.frames.len() >= self.max_call_depth {
+                    let trace = std::iter::once(name)
+                        .chain(self.frames.iter().rev().map(|frame| frame.name.to_owned()))
+                        .take(STACK_OVERFLOW_TRACE_LEN)
+                        .collect();
+                    return Err(Spanned::new(Error::StackOverflow(trace), span));
+                }
                 let mut subst = Scope::new();
                 subst.vars.extend(args_evaluated);
-                self.stack.push(subst);
-                let result = match self.eval(&func.body)? {
+                // The body sees the scopes from its definition, not the ones of the caller
+                let mut stack = func.closure.clone();
+                stack.push(Rc::new(RefCell::new(subst)));
+                let caller_stack = std::mem::replace(&mut self.stack, stack);
+                self.frames.push(CallFrame { name, span });
+                let result = self.eval(&func.body).and_then(|value| match value {
                     Value::EvaluatedReturn(val) => Ok(*val),
+                    Value::Break => Err(Spanned::new(
+                        Error::BreakOutsideLoop,
+                        func.body.span.to_owned(),
+                    )),
+                    Value::Continue => Err(Spanned::new(
+                        Error::ContinueOutsideLoop,
+                        func.body.span.to_owned(),
+                    )),
                     other => Ok(other),
-                };
-                self.stack.pop();
+                });
+                // The innermost function that failed sees the whole trace
+                if result.is_err() && self.error_trace.is_none() {
+                    self.error_trace = Some(self.frames.clone());
+                }
+                self.frames.pop();
+                let mut stack = std::mem::replace(&mut self.stack, caller_stack);
+                release_scope(stack.pop().unwrap());
                 result
             }
+            // Compiled functions need the state of the virtual machine
+            Callable::Compiled(_) => Err(Spanned::new(
+                Error::ThisCannotBeCalled(callable.to_string()),
+                span,
+            )),
         }
     }
 
     pub fn import_library(&mut self, lib: Library, prefix_name: bool) {
         let libname = lib.name;
-        self.stack[0]
+        let mut global = self.stack[0].borrow_mut();
+        global
             .vars
             .extend(lib.scope.vars.into_iter().map(|(key, value)| {
                 (
@@ -438,7 +845,7 @@ impl Context {
                     value,
                 )
             }));
-        self.stack[0]
+        global
             .vars_props
             .extend(lib.scope.vars_props.into_iter().map(|prop| {
                 if prefix_name {
@@ -460,120 +867,240 @@ impl Context {
         let span_b = expr_b.span.clone();
         let a = &self.eval(&expr_a)?;
         let b = &self.eval(&expr_b)?;
-        match a {
-            Value::Int(val_a) => match b {
-                Value::Int(val_b) => match op {
-                    MathOperator::Addition => Ok(Value::Int(val_a + val_b)),
-                    MathOperator::Subtraction => Ok(Value::Int(val_a - val_b)),
-                    MathOperator::Multiply => Ok(Value::Int(val_a * val_b)),
-                    MathOperator::Division => Ok(Value::Int(val_a / val_b)),
-                    MathOperator::Eq => Ok(Value::Bool(val_a == val_b)),
-                    MathOperator::Neq => Ok(Value::Bool(val_a != val_b)),
-                    MathOperator::Lt => Ok(Value::Bool(val_a < val_b)),
-                    MathOperator::Gt => Ok(Value::Bool(val_a > val_b)),
-                    MathOperator::Lte => Ok(Value::Bool(val_a <= val_b)),
-                    MathOperator::Gte => Ok(Value::Bool(val_a >= val_b)),
-                },
-                Value::Float(val_b) => match op {
-                    MathOperator::Addition => Ok(Value::Float(f64::from(*val_a) + val_b)),
-                    MathOperator::Subtraction => Ok(Value::Float(f64::from(*val_a) - val_b)),
-                    MathOperator::Multiply => Ok(Value::Float(f64::from(*val_a) * val_b)),
-                    MathOperator::Division => Ok(Value::Float(f64::from(*val_a) / val_b)),
-                    MathOperator::Eq => Ok(Value::Bool(f64::from(*val_a) == *val_b)),
-                    MathOperator::Neq => Ok(Value::Bool(f64::from(*val_a) != *val_b)),
-                    MathOperator::Lt => Ok(Value::Bool(f64::from(*val_a) < *val_b)),
-                    MathOperator::Gt => Ok(Value::Bool(f64::from(*val_a) > *val_b)),
-                    MathOperator::Lte => Ok(Value::Bool(f64::from(*val_a) <= *val_b)),
-                    MathOperator::Gte => Ok(Value::Bool(f64::from(*val_a) >= *val_b)),
-                },
-                _ => Err(Spanned::new(
-                    Error::TypeError(
-                        format!(
-                            "Incompatible types ({} and {}) for this operator",
-                            a.type_to_string(),
-                            b.type_to_string()
-                        )
-                        .to_owned(),
-                    ),
-                    span_b,
+        apply_math_operator(a, b, op, span_a, span_b)
+    }
+}
+
+/// Drops the variables of a finished call if only functions stored in it keep it alive
+fn release_scope(scope: ScopeRef) {
+    if Rc::strong_count(&scope) == 1 {
+        return;
+    }
+    let mut graph = cycles::Graph::new(&scope);
+    let root = graph.root();
+    for value in scope.borrow().vars.values() {
+        graph.value(root, value, &mut |graph, parent, callable| {
+            walk_function(graph, parent, callable, &scope)
+        });
+    }
+    if !graph.root_is_alive() {
+        let vars = std::mem::take(&mut scope.borrow_mut().vars);
+        drop(vars);
+    }
+}
+
+/// Walks the scopes that a function captured inside the released one
+fn walk_function(
+    graph: &mut cycles::Graph,
+    parent: cycles::NodeId,
+    callable: &Callable,
+    released: &ScopeRef,
+) {
+    let Callable::Function(func) = callable else {
+        return;
+    };
+    let Some(index) = func
+        .closure
+        .iter()
+        .position(|scope| Rc::ptr_eq(scope, released))
+    else {
+        return;
+    };
+    for scope in &func.closure[index..] {
+        if let Some(id) = graph.reference(parent, scope) {
+            for value in scope.borrow().vars.values() {
+                graph.value(id, value, &mut |graph, parent, callable| {
+                    walk_function(graph, parent, callable, released)
+                });
+            }
+        }
+    }
+}
+
+/// Applies a binary operator to already evaluated operands
+pub(crate) fn apply_math_operator(
+    a: &Value,
+    b: &Value,
+    op: MathOperator,
+    span_a: Range<usize>,
+    span_b: Range<usize>,
+) -> Result<Value, Spanned<Error>> {
+    let span = span_a.start..span_b.end;
+    // Any value can be compared with None
+    if matches!(a, Value::None) || matches!(b, Value::None) {
+        let both_none = matches!(a, Value::None) && matches!(b, Value::None);
+        match op {
+            MathOperator::Eq => return Ok(Value::Bool(both_none)),
+            MathOperator::Neq => return Ok(Value::Bool(!both_none)),
+            _ => {}
+        }
+    }
+    match a {
+        Value::Int(val_a) => match b {
+            Value::Int(val_b) => int_operator(*val_a, *val_b, op)
+                .map_err(|err| Spanned::new(Error::RuntimeError(err), span.to_owned())),
+            Value::Float(val_b) => match op {
+                MathOperator::Addition => Ok(Value::Float(f64::from(*val_a) + val_b)),
+                MathOperator::Subtraction => Ok(Value::Float(f64::from(*val_a) - val_b)),
+                MathOperator::Multiply => Ok(Value::Float(f64::from(*val_a) * val_b)),
+                MathOperator::Division => Ok(Value::Float(f64::from(*val_a) / val_b)),
+                MathOperator::Modulo => {
+                    Ok(Value::Float(float_floor_mod(f64::from(*val_a), *val_b)))
+                }
+                MathOperator::IntDivision => Ok(Value::Float((f64::from(*val_a) / val_b).floor())),
+                MathOperator::Power => Ok(Value::Float(f64::from(*val_a).powf(*val_b))),
+                MathOperator::Eq => Ok(Value::Bool(f64::from(*val_a) == *val_b)),
+                MathOperator::Neq => Ok(Value::Bool(f64::from(*val_a) != *val_b)),
+                MathOperator::Lt => Ok(Value::Bool(f64::from(*val_a) < *val_b)),
+                MathOperator::Gt => Ok(Value::Bool(f64::from(*val_a) > *val_b)),
+                MathOperator::Lte => Ok(Value::Bool(f64::from(*val_a) <= *val_b)),
+                MathOperator::Gte => Ok(Value::Bool(f64::from(*val_a) >= *val_b)),
+            },
+            _ => Err(Spanned::new(
+                Error::TypeError(TypeErrorKind::Operands(
+                    a.type_to_string().to_owned(),
+                    b.type_to_string().to_owned(),
                 )),
+                span_b,
+            )),
+        },
+        Value::Float(val_a) => match b {
+            Value::Float(val_b) => match op {
+                MathOperator::Addition => Ok(Value::Float(val_a + val_b)),
+                MathOperator::Subtraction => Ok(Value::Float(val_a - val_b)),
+                MathOperator::Multiply => Ok(Value::Float(val_a * val_b)),
+                MathOperator::Division => Ok(Value::Float(val_a / val_b)),
+                MathOperator::Modulo => Ok(Value::Float(float_floor_mod(*val_a, *val_b))),
+                MathOperator::IntDivision => Ok(Value::Float((val_a / val_b).floor())),
+                MathOperator::Power => Ok(Value::Float(val_a.powf(*val_b))),
+                MathOperator::Eq => Ok(Value::Bool(val_a == val_b)),
+                MathOperator::Neq => Ok(Value::Bool(val_a != val_b)),
+                MathOperator::Lt => Ok(Value::Bool(val_a < val_b)),
+                MathOperator::Gt => Ok(Value::Bool(val_a > val_b)),
+                MathOperator::Lte => Ok(Value::Bool(val_a <= val_b)),
+                MathOperator::Gte => Ok(Value::Bool(val_a >= val_b)),
+            },
+            Value::Int(val_b) => match op {
+                MathOperator::Addition => Ok(Value::Float(val_a + f64::from(*val_b))),
+                MathOperator::Subtraction => Ok(Value::Float(val_a - f64::from(*val_b))),
+                MathOperator::Multiply => Ok(Value::Float(val_a * f64::from(*val_b))),
+                MathOperator::Division => Ok(Value::Float(val_a / f64::from(*val_b))),
+                MathOperator::Modulo => {
+                    Ok(Value::Float(float_floor_mod(*val_a, f64::from(*val_b))))
+                }
+                MathOperator::IntDivision => Ok(Value::Float((val_a / f64::from(*val_b)).floor())),
+                MathOperator::Power => Ok(Value::Float(val_a.powi(*val_b))),
+                MathOperator::Eq => Ok(Value::Bool(*val_a == f64::from(*val_b))),
+                MathOperator::Neq => Ok(Value::Bool(*val_a != f64::from(*val_b))),
+                MathOperator::Lt => Ok(Value::Bool(*val_a < f64::from(*val_b))),
+                MathOperator::Gt => Ok(Value::Bool(*val_a > f64::from(*val_b))),
+                MathOperator::Lte => Ok(Value::Bool(*val_a <= f64::from(*val_b))),
+                MathOperator::Gte => Ok(Value::Bool(*val_a >= f64::from(*val_b))),
             },
-            Value::Float(val_a) => match b {
-                Value::Float(val_b) => match op {
-                    MathOperator::Addition => Ok(Value::Float(val_a + val_b)),
-                    MathOperator::Subtraction => Ok(Value::Float(val_a - val_b)),
-                    MathOperator::Multiply => Ok(Value::Float(val_a * val_b)),
-                    MathOperator::Division => Ok(Value::Float(val_a / val_b)),
-                    MathOperator::Eq => Ok(Value::Bool(val_a == val_b)),
-                    MathOperator::Neq => Ok(Value::Bool(val_a != val_b)),
-                    MathOperator::Lt => Ok(Value::Bool(val_a < val_b)),
-                    MathOperator::Gt => Ok(Value::Bool(val_a > val_b)),
-                    MathOperator::Lte => Ok(Value::Bool(val_a <= val_b)),
-                    MathOperator::Gte => Ok(Value::Bool(val_a >= val_b)),
-                },
-                Value::Int(val_b) => match op {
-                    MathOperator::Addition => Ok(Value::Float(val_a + f64::from(*val_b))),
-                    MathOperator::Subtraction => Ok(Value::Float(val_a - f64::from(*val_b))),
-                    MathOperator::Multiply => Ok(Value::Float(val_a * f64::from(*val_b))),
-                    MathOperator::Division => Ok(Value::Float(val_a / f64::from(*val_b))),
-                    MathOperator::Eq => Ok(Value::Bool(*val_a == f64::from(*val_b))),
-                    MathOperator::Neq => Ok(Value::Bool(*val_a != f64::from(*val_b))),
-                    MathOperator::Lt => Ok(Value::Bool(*val_a < f64::from(*val_b))),
-                    MathOperator::Gt => Ok(Value::Bool(*val_a > f64::from(*val_b))),
-                    MathOperator::Lte => Ok(Value::Bool(*val_a <= f64::from(*val_b))),
-                    MathOperator::Gte => Ok(Value::Bool(*val_a >= f64::from(*val_b))),
-                },
+            _ => Err(Spanned::new(
+                Error::TypeError(TypeErrorKind::Operands(
+                    a.type_to_string().to_owned(),
+                    b.type_to_string().to_owned(),
+                )),
+                span_b,
+            )),
+        },
+        Value::String(val_a) => match b {
+            Value::String(val_b) => match op {
+                MathOperator::Addition => Ok(Value::String(val_a.to_owned() + val_b)),
+                MathOperator::Eq => Ok(Value::Bool(val_a == val_b)),
+                MathOperator::Neq => Ok(Value::Bool(val_a != val_b)),
                 _ => Err(Spanned::new(
-                    Error::TypeError(
-                        format!(
-                            "Incompatible types ({} and {}) for this operator",
-                            a.type_to_string(),
-                            b.type_to_string()
-                        )
-                        .to_owned(),
-                    ),
+                    Error::TypeError(TypeErrorKind::Operands(
+                        a.type_to_string().to_owned(),
+                        b.type_to_string().to_owned(),
+                    )),
                     span_b,
                 )),
             },
-            Value::String(val_a) => match b {
-                Value::String(val_b) => match op {
-                    MathOperator::Addition => Ok(Value::String(val_a.to_owned() + val_b)),
-                    MathOperator::Eq => Ok(Value::Bool(val_a == val_b)),
-                    MathOperator::Neq => Ok(Value::Bool(val_a != val_b)),
-                    _ => Err(Spanned::new(
-                        Error::TypeError(
-                            format!(
-                                "Incompatible types ({} and {}) for this operator",
-                                a.type_to_string(),
-                                b.type_to_string()
-                            )
-                            .to_owned(),
-                        ),
-                        span_b,
-                    )),
-                },
+            _ => Err(Spanned::new(
+                Error::TypeError(TypeErrorKind::Operands(
+                    a.type_to_string().to_owned(),
+                    b.type_to_string().to_owned(),
+                )),
+                span_b,
+            )),
+        },
+        Value::Bool(val_a) => match b {
+            Value::Bool(val_b) => match op {
+                MathOperator::Eq => Ok(Value::Bool(val_a == val_b)),
+                MathOperator::Neq => Ok(Value::Bool(val_a != val_b)),
                 _ => Err(Spanned::new(
-                    Error::TypeError(
-                        format!(
-                            "Incompatible types ({} and {}) for this operator",
-                            a.type_to_string(),
-                            b.type_to_string()
-                        )
-                        .to_owned(),
-                    ),
+                    Error::TypeError(TypeErrorKind::Operands(
+                        a.type_to_string().to_owned(),
+                        b.type_to_string().to_owned(),
+                    )),
                     span_b,
                 )),
             },
             _ => Err(Spanned::new(
-                Error::TypeError(
-                    format!(
-                        "Operand A (type {}) does not support this operator",
-                        a.type_to_string()
-                    )
-                    .to_owned(),
-                ),
-                span_a,
+                Error::TypeError(TypeErrorKind::Operands(
+                    a.type_to_string().to_owned(),
+                    b.type_to_string().to_owned(),
+                )),
+                span_b,
             )),
+        },
+        _ => Err(Spanned::new(
+            Error::TypeError(TypeErrorKind::Operand(a.type_to_string().to_owned())),
+            span_a,
+        )),
+    }
+}
+
+/// Integer operators are checked, overflows are reported instead of switching to floats
+fn int_operator(a: i32, b: i32, op: MathOperator) -> Result<Value, RuntimeError> {
+    let checked = |result: Option<i32>| result.map(Value::Int).ok_or(RuntimeError::IntegerOverflow);
+    match op {
+        MathOperator::Addition => checked(a.checked_add(b)),
+        MathOperator::Subtraction => checked(a.checked_sub(b)),
+        MathOperator::Multiply => checked(a.checked_mul(b)),
+        MathOperator::Division | MathOperator::Modulo | MathOperator::IntDivision if b == 0 => {
+            Err(RuntimeError::DivisionByZero)
         }
+        MathOperator::Division => checked(a.checked_div(b)),
+        MathOperator::Modulo => checked(int_floor_mod(a, b)),
+        MathOperator::IntDivision => checked(int_floor_div(a, b)),
+        // Negative exponents produce fractions
+        MathOperator::Power => match u32::try_from(b) {
+            Ok(exp) => checked(a.checked_pow(exp)),
+            Err(_) if a == 0 => Err(RuntimeError::DivisionByZero),
+            Err(_) => Ok(Value::Float(f64::from(a).powi(b))),
+        },
+        MathOperator::Eq => Ok(Value::Bool(a == b)),
+        MathOperator::Neq => Ok(Value::Bool(a != b)),
+        MathOperator::Lt => Ok(Value::Bool(a < b)),
+        MathOperator::Gt => Ok(Value::Bool(a > b)),
+        MathOperator::Lte => Ok(Value::Bool(a <= b)),
+        MathOperator::Gte => Ok(Value::Bool(a >= b)),
+    }
+}
+
+/// Integer division rounding towards negative infinity
+fn int_floor_div(a: i32, b: i32) -> Option<i32> {
+    let quotient = a.checked_div(b)?;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        quotient.checked_sub(1)
+    } else {
+        Some(quotient)
     }
 }
+
+/// Remainder with the sign of the divisor, so that `-1 % 16 == 15`
+fn int_floor_mod(a: i32, b: i32) -> Option<i32> {
+    let remainder = a.checked_rem(b)?;
+    if remainder != 0 && (remainder < 0) != (b < 0) {
+        Some(remainder + b)
+    } else {
+        Some(remainder)
+    }
+}
+
+fn float_floor_mod(a: f64, b: f64) -> f64 {
+    a - b * (a / b).floor()
+}
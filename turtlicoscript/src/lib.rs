@@ -1,8 +1,15 @@
 pub mod ast;
+pub mod bytecode;
+mod cycles;
+pub mod diagnostics;
 pub mod error;
 pub mod interpreter;
+pub mod locale;
+pub mod optimizer;
 pub mod parser;
+pub mod resolver;
 pub mod tokens;
 pub mod stdlib;
 pub mod value;
+pub mod vm;
 mod tests;
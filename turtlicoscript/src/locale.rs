@@ -0,0 +1,329 @@
+//! Translated texts of error messages.
+//! A new language is added by a new [`Language`] variant and a function that returns
+//! the text of every [`Message`].
+
+use crate::tokens::{escape_string, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    English,
+    Czech,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Czech];
+
+    /// Name of the language in the language itself
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Czech => "Čeština",
+        }
+    }
+
+    /// ISO 639-1 code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Czech => "cs",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Language> {
+        Language::ALL.into_iter().find(|lang| lang.code() == code)
+    }
+}
+
+/// Keys of the catalog. `{0}`, `{1}` are replaced by arguments of the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    // Syntax errors
+    InvalidSyntax,
+    InvalidToken,
+    InvalidNumber,
+    ExpectedVariable,
+    InvalidEscape,
+    Unexpected,
+    ExpectedFound,
+    Unclosed,
+    EndOfProgram,
+    EndOfLine,
+    Comment,
+    Or,
+    UnmatchedBrace,
+    ExpectedOneExpression,
+    InContext,
+    IfCondition,
+    ForHeader,
+    FunctionArgs,
+    // Errors of the interpreter
+    ThisCannotBeCalled,
+    ThisIsNotAssignable,
+    InvalidIterationCount,
+    InvalidForStart,
+    InvalidForEnd,
+    InvalidForStep,
+    InvalidForCollection,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    StackOverflow,
+    OperandType,
+    OperandTypes,
+    NotIndexable,
+    ListIndexType,
+    NotAnObject,
+    PropertyNotCallable,
+    Interrupted,
+    EngineChanged,
+    BudgetExhausted,
+    CallStack,
+    UnreachableCode,
+    // Diagnostics
+    Error,
+    Warning,
+    Location,
+    // Runtime errors
+    InvalidArgCount,
+    InvalidArgType,
+    MissingParam,
+    ExtraParam,
+    InvalidIdentifier,
+    TypeParseError,
+    TypeParseUnsupported,
+    TypeHashUnsupported,
+    WrongType,
+    InvalidBlock,
+    NativeLibraryError,
+    MethodCalledAsFunction,
+    InvalidKey,
+    InvalidTilemap,
+    InvalidFlag,
+    IndexOutOfRange,
+    EmptyList,
+    DivisionByZero,
+    IntegerOverflow,
+}
+
+impl Message {
+    pub fn text(self, lang: Language) -> &'static str {
+        match lang {
+            Language::English => english(self),
+            Language::Czech => czech(self),
+        }
+    }
+
+    /// Text with `{0}`, `{1}`... replaced by the arguments
+    pub fn format(self, lang: Language, args: &[String]) -> String {
+        let mut text = self.text(lang).to_owned();
+        for (i, arg) in args.iter().enumerate() {
+            text = text.replace(&format!("{{{}}}", i), arg);
+        }
+        text
+    }
+}
+
+fn english(msg: Message) -> &'static str {
+    match msg {
+        Message::InvalidSyntax => "Invalid syntax: {0}",
+        Message::InvalidToken => "Invalid token",
+        Message::InvalidNumber => "{0} is not a valid number",
+        Message::ExpectedVariable => "Expected a variable or a field, not a function call",
+        Message::InvalidEscape => "Invalid escape sequence in {0}",
+        Message::Unexpected => "Unexpected {0}",
+        Message::ExpectedFound => "Expected {0}, found {1}",
+        Message::Unclosed => "{0} is not closed",
+        Message::EndOfProgram => "end of program",
+        Message::EndOfLine => "end of line",
+        Message::Comment => "comment",
+        Message::Or => "or",
+        Message::UnmatchedBrace => "Unmatched brace in a string",
+        Message::ExpectedOneExpression => "Expected one expression",
+        Message::InContext => "{0} ({1})",
+        Message::IfCondition => "in the condition of if",
+        Message::ForHeader => "in the header of the for loop",
+        Message::FunctionArgs => "in the arguments of the function",
+        Message::ThisCannotBeCalled => "{0} cannot be called",
+        Message::ThisIsNotAssignable => {
+            "Only variables, object fields and list items can be assigned to"
+        }
+        Message::InvalidIterationCount => "Number of repetitions must be an integer",
+        Message::InvalidForStart => "Start of the for loop must be an integer",
+        Message::InvalidForEnd => "End of the for loop must be an integer",
+        Message::InvalidForStep => "Step of the for loop must be an integer",
+        Message::InvalidForCollection => {
+            "The for loop can go only through a list, an object or a string"
+        }
+        Message::BreakOutsideLoop => "break can be used only inside a loop",
+        Message::ContinueOutsideLoop => "continue can be used only inside a loop",
+        Message::StackOverflow => {
+            "Too many nested function calls, the function probably calls itself forever: {0}"
+        }
+        Message::OperandType => "This operator cannot be used on {0}",
+        Message::OperandTypes => "This operator cannot be used on {0} and {1}",
+        Message::NotIndexable => "{0} cannot be indexed",
+        Message::ListIndexType => "List index must be an int, not {0}",
+        Message::NotAnObject => "This is not an object",
+        Message::PropertyNotCallable => "This property is not a function",
+        Message::Interrupted => "The program was stopped",
+        Message::EngineChanged => "The engine cannot be changed after a program ran in the same context",
+        Message::BudgetExhausted => "The program ran out of its time or step limit after {0} steps",
+        Message::CallStack => "Call stack (the innermost call last):",
+        Message::UnreachableCode => "This code is never run",
+        Message::Error => "error",
+        Message::Warning => "warning",
+        Message::Location => "line {0}, column {1}",
+        Message::InvalidArgCount => "Wrong number of arguments: got {0}, expected {1}",
+        Message::InvalidArgType => "Argument {0} has a wrong type",
+        Message::MissingParam => "Missing parameter {0}",
+        Message::ExtraParam => "Unknown parameter {0}",
+        Message::InvalidIdentifier => "{0} is not defined",
+        Message::TypeParseError => "The value cannot be converted: {0}",
+        Message::TypeParseUnsupported => "{0} cannot be converted to {1}",
+        Message::TypeHashUnsupported => "This value cannot be used as a key",
+        Message::WrongType => "The value has a wrong type",
+        Message::InvalidBlock => "Unknown block {0}",
+        Message::NativeLibraryError => "Library error: {0}",
+        Message::MethodCalledAsFunction => "This function must be called on an object",
+        Message::InvalidKey => "Unknown key",
+        Message::InvalidTilemap => "Invalid tilemap",
+        Message::InvalidFlag => "Unknown option of the function",
+        Message::IndexOutOfRange => "Index {0} is out of range of a list with {1} items",
+        Message::EmptyList => "The list is empty",
+        Message::DivisionByZero => "Division by zero",
+        Message::IntegerOverflow => "The result is too large for an integer",
+    }
+}
+
+fn czech(msg: Message) -> &'static str {
+    match msg {
+        Message::InvalidSyntax => "Chybný zápis programu: {0}",
+        Message::InvalidToken => "Neplatný symbol",
+        Message::InvalidNumber => "{0} není platné číslo",
+        Message::ExpectedVariable => "Očekávána proměnná nebo vlastnost, ne volání funkce",
+        Message::InvalidEscape => "Neplatná escape sekvence v {0}",
+        Message::Unexpected => "Neočekávaný {0}",
+        Message::ExpectedFound => "Očekáváno {0}, nalezeno {1}",
+        Message::Unclosed => "{0} není uzavřeno",
+        Message::EndOfProgram => "konec programu",
+        Message::EndOfLine => "konec řádku",
+        Message::Comment => "komentář",
+        Message::Or => "nebo",
+        Message::UnmatchedBrace => "Neuzavřená složená závorka v řetězci",
+        Message::ExpectedOneExpression => "Očekáván jeden výraz",
+        Message::InContext => "{0} ({1})",
+        Message::IfCondition => "v podmínce if",
+        Message::ForHeader => "v hlavičce cyklu for",
+        Message::FunctionArgs => "v argumentech funkce",
+        Message::ThisCannotBeCalled => "{0} nelze zavolat",
+        Message::ThisIsNotAssignable => {
+            "Přiřadit lze jen do proměnné, vlastnosti objektu nebo prvku seznamu"
+        }
+        Message::InvalidIterationCount => "Počet opakování musí být celé číslo",
+        Message::InvalidForStart => "Začátek cyklu for musí být celé číslo",
+        Message::InvalidForEnd => "Konec cyklu for musí být celé číslo",
+        Message::InvalidForStep => "Krok cyklu for musí být celé číslo",
+        Message::InvalidForCollection => "Cyklus for umí procházet jen seznam, objekt nebo řetězec",
+        Message::BreakOutsideLoop => "break lze použít jen uvnitř cyklu",
+        Message::ContinueOutsideLoop => "continue lze použít jen uvnitř cyklu",
+        Message::StackOverflow => {
+            "Příliš mnoho vnořených volání funkcí, funkce nejspíš volá sama sebe donekonečna: {0}"
+        }
+        Message::OperandType => "Tento operátor nelze použít na typ {0}",
+        Message::OperandTypes => "Tento operátor nelze použít na typy {0} a {1}",
+        Message::NotIndexable => "Typ {0} nelze indexovat",
+        Message::ListIndexType => "Index seznamu musí být int, ne {0}",
+        Message::NotAnObject => "Toto není objekt",
+        Message::PropertyNotCallable => "Tato vlastnost není funkce",
+        Message::Interrupted => "Program byl zastaven",
+        Message::EngineChanged => "Po spuštění programu nelze ve stejném kontextu změnit interpret",
+        Message::BudgetExhausted => "Program vyčerpal limit času nebo kroků po {0} krocích",
+        Message::CallStack => "Zásobník volání (poslední je nejvnitřnější volání):",
+        Message::UnreachableCode => "Tento kód se nikdy neprovede",
+        Message::Error => "chyba",
+        Message::Warning => "varování",
+        Message::Location => "řádek {0}, sloupec {1}",
+        Message::InvalidArgCount => "Špatný počet argumentů: zadáno {0}, očekáváno {1}",
+        Message::InvalidArgType => "Argument {0} má špatný typ",
+        Message::MissingParam => "Chybí parametr {0}",
+        Message::ExtraParam => "Neznámý parametr {0}",
+        Message::InvalidIdentifier => "{0} není definováno",
+        Message::TypeParseError => "Hodnotu nelze převést: {0}",
+        Message::TypeParseUnsupported => "{0} nelze převést na {1}",
+        Message::TypeHashUnsupported => "Tuto hodnotu nelze použít jako klíč",
+        Message::WrongType => "Hodnota má špatný typ",
+        Message::InvalidBlock => "Neznámý blok {0}",
+        Message::NativeLibraryError => "Chyba knihovny: {0}",
+        Message::MethodCalledAsFunction => "Tuto funkci je nutné zavolat na objektu",
+        Message::InvalidKey => "Neznámá klávesa",
+        Message::InvalidTilemap => "Neplatná mapa dlaždic",
+        Message::InvalidFlag => "Neznámá volba funkce",
+        Message::IndexOutOfRange => "Index {0} je mimo rozsah seznamu s {1} prvky",
+        Message::EmptyList => "Seznam je prázdný",
+        Message::DivisionByZero => "Dělení nulou",
+        Message::IntegerOverflow => "Výsledek je na celé číslo příliš velký",
+    }
+}
+
+/// How a token is written in the program, used in syntax errors
+pub fn describe_token(token: &Token, lang: Language) -> String {
+    let text = match token {
+        Token::Comment => return Message::Comment.text(lang).to_owned(),
+        Token::Newline => return Message::EndOfLine.text(lang).to_owned(),
+        Token::Space => ";",
+        Token::If => "if",
+        Token::Else => "else",
+        Token::Return => "return",
+        Token::Break => "break",
+        Token::Continue => "continue",
+        Token::Loop => "loop",
+        Token::For => "for",
+        Token::In => "in",
+        Token::While => "while",
+        Token::Local => "local",
+        Token::Global => "global",
+        Token::FnDef => "fn",
+        Token::True => "true",
+        Token::False => "false",
+        Token::Variable(name) => return format!("${}", name),
+        Token::Image(name) => return format!("@\"{}\"", escape_string(name)),
+        Token::Key(name) => return format!("k\"{}\"", escape_string(name)),
+        Token::File(name) => return format!("f\"{}\"", escape_string(name)),
+        Token::Tilemap(name) => return format!("s\"{}\"", name),
+        Token::Function(name) => return name.to_owned(),
+        Token::String(text) => return format!("\"{}\"", escape_string(text)),
+        Token::Integer(value) => return value.to_string(),
+        Token::Float(value) => return value.to_owned(),
+        Token::LeftParent => "(",
+        Token::RightParent => ")",
+        Token::LeftCurly => "{",
+        Token::RightCurly => "}",
+        Token::LeftSquare => "[",
+        Token::RightSquare => "]",
+        Token::Comma => ",",
+        Token::Colon => ":",
+        Token::Dot => ".",
+        Token::Plus => "+",
+        Token::Minus => "-",
+        Token::Star => "*",
+        Token::Slash => "/",
+        Token::Percent => "%",
+        Token::DoubleSlash => "//",
+        Token::DoubleStar => "**",
+        Token::Eq => "==",
+        Token::Neq => "!=",
+        Token::Lt => "<",
+        Token::Gt => ">",
+        Token::Lte => "<=",
+        Token::Gte => ">=",
+        Token::Assignment => "=",
+        Token::PlusAssignment => "+=",
+        Token::MinusAssignment => "-=",
+        Token::StarAssignment => "*=",
+        Token::SlashAssignment => "/=",
+        Token::And => "and",
+        Token::Or => "or",
+        Token::Not => "not",
+    };
+    text.to_owned()
+}
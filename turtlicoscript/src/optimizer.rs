@@ -0,0 +1,117 @@
+//! Simplifies the syntax tree before it is run.
+//!
+//! Operators on literals are replaced by their results and `if` with a literal condition by the
+//! branch that runs. A folded expression keeps the span of the code it replaced, operations that
+//! fail (e.g. division by zero) are left in place so they report the error at runtime.
+
+use crate::{
+    ast::{Expression, Spanned},
+    interpreter::{apply_math_operator, MathOperator},
+    value::Value,
+};
+
+/// Returns the simplified program
+pub fn optimized(mut program: Spanned<Expression>) -> Spanned<Expression> {
+    optimize(&mut program);
+    program
+}
+
+/// Simplifies the program in place
+pub fn optimize(expression: &mut Spanned<Expression>) {
+    for child in expression.item.children_mut() {
+        optimize(child);
+    }
+    if let Some(folded) = fold(&expression.item) {
+        expression.item = folded;
+    }
+}
+
+fn fold(expression: &Expression) -> Option<Expression> {
+    match expression {
+        Expression::Negation(a) => match a.item {
+            Expression::Int(val) => val.checked_neg().map(Expression::Int),
+            Expression::Float(val) => Some(Expression::Float(-val)),
+            _ => None,
+        },
+        Expression::Not(a) => match a.item {
+            Expression::Bool(val) => Some(Expression::Bool(!val)),
+            _ => None,
+        },
+        // The right operand is not evaluated when the left one decides the result
+        Expression::And(a, b) => match (&a.item, &b.item) {
+            (Expression::Bool(false), _) => Some(Expression::Bool(false)),
+            (Expression::Bool(true), Expression::Bool(val)) => Some(Expression::Bool(*val)),
+            _ => None,
+        },
+        Expression::Or(a, b) => match (&a.item, &b.item) {
+            (Expression::Bool(true), _) => Some(Expression::Bool(true)),
+            (Expression::Bool(false), Expression::Bool(val)) => Some(Expression::Bool(*val)),
+            _ => None,
+        },
+        Expression::If {
+            cond,
+            body,
+            else_body,
+        } => {
+            let cond = literal(&cond.item)?;
+            Some(if matches!(cond, Value::Bool(true)) {
+                body.item.clone()
+            } else {
+                else_body
+                    .as_ref()
+                    .map_or(Expression::None, |else_body| else_body.item.clone())
+            })
+        }
+        _ => {
+            let (a, b, op) = math_operator(expression)?;
+            let result = apply_math_operator(
+                &literal(&a.item)?,
+                &literal(&b.item)?,
+                op,
+                a.span.to_owned(),
+                b.span.to_owned(),
+            );
+            match result.ok()? {
+                Value::Int(val) => Some(Expression::Int(val)),
+                Value::Float(val) => Some(Expression::Float(val)),
+                Value::Bool(val) => Some(Expression::Bool(val)),
+                Value::String(val) => Some(Expression::String(val)),
+                Value::None => Some(Expression::None),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn math_operator(
+    expression: &Expression,
+) -> Option<(&Spanned<Expression>, &Spanned<Expression>, MathOperator)> {
+    match expression {
+        Expression::Addition(a, b) => Some((a, b, MathOperator::Addition)),
+        Expression::Subtraction(a, b) => Some((a, b, MathOperator::Subtraction)),
+        Expression::Multiply(a, b) => Some((a, b, MathOperator::Multiply)),
+        Expression::Division(a, b) => Some((a, b, MathOperator::Division)),
+        Expression::Modulo(a, b) => Some((a, b, MathOperator::Modulo)),
+        Expression::IntDivision(a, b) => Some((a, b, MathOperator::IntDivision)),
+        Expression::Power(a, b) => Some((a, b, MathOperator::Power)),
+        Expression::Eq(a, b) => Some((a, b, MathOperator::Eq)),
+        Expression::Neq(a, b) => Some((a, b, MathOperator::Neq)),
+        Expression::Lt(a, b) => Some((a, b, MathOperator::Lt)),
+        Expression::Gt(a, b) => Some((a, b, MathOperator::Gt)),
+        Expression::Lte(a, b) => Some((a, b, MathOperator::Lte)),
+        Expression::Gte(a, b) => Some((a, b, MathOperator::Gte)),
+        _ => None,
+    }
+}
+
+/// Value of a literal that can be folded
+fn literal(expression: &Expression) -> Option<Value> {
+    match expression {
+        Expression::Int(val) => Some(Value::Int(*val)),
+        Expression::Float(val) => Some(Value::Float(*val)),
+        Expression::Bool(val) => Some(Value::Bool(*val)),
+        Expression::String(val) => Some(Value::String(val.to_owned())),
+        Expression::None => Some(Value::None),
+        _ => None,
+    }
+}
@@ -1,12 +1,20 @@
 use std::num::ParseFloatError;
 use std::ops::Range;
 
-use chumsky::{prelude::*, Stream};
+use chumsky::{error::SimpleReason, prelude::*, Stream};
 use logos::Logos;
 
-use crate::ast::{Expression, Spanned};
-use crate::error::Error;
-use crate::tokens::Token;
+use crate::ast::{AssignmentOperator, Expression, Spanned, VariableScope};
+use crate::error::{Error, SyntaxContext};
+use crate::tokens::{unescape_string_offsets, LexingError, Token};
+
+// Texts of custom errors, converted to errors of the language by `syntax_error`
+const INVALID_TOKEN: &str = "Invalid token";
+const UNMATCHED_BRACE: &str = "Unmatched brace in a string";
+const EXPECTED_ONE_EXPRESSION: &str = "Expected one expression";
+const EXPECTED_VARIABLE: &str = "Not a variable";
+/// Followed by the text of the literal
+const INVALID_NUMBER: &str = "Invalid number: ";
 
 pub fn parse(source: &str) -> Result<Spanned<Expression>, Vec<Spanned<Error>>> {
     let lexer = Token::lexer(source);
@@ -14,7 +22,7 @@ pub fn parse(source: &str) -> Result<Spanned<Expression>, Vec<Spanned<Error>>> {
 
     let lexer_out = lexer
         .spanned()
-        .collect::<Vec<(Result<Token, ()>, Range<usize>)>>();
+        .collect::<Vec<(Result<Token, LexingError>, Range<usize>)>>();
     let mut tokens = vec![];
     let mut errors = vec![];
     for (token, span) in lexer_out {
@@ -22,50 +30,46 @@ pub fn parse(source: &str) -> Result<Spanned<Expression>, Vec<Spanned<Error>>> {
             Ok(t) => {
                 tokens.push((t, span));
             }
-            Err(_) => {
+            Err(LexingError::InvalidToken) => {
                 errors.push(Spanned::new(Error::InvalidToken, span));
             }
+            Err(LexingError::InvalidEscape(range)) => {
+                let span = span.start + range.start..span.start + range.end;
+                errors.push(Spanned::new(
+                    Error::InvalidEscape(source[span.clone()].to_owned()),
+                    span,
+                ));
+            }
         }
     }
+    errors.extend(check_string_literals(&tokens, Some(source)));
     if errors.len() > 0 {
         return Err(errors);
     }
 
-    create_parser()
+    create_parser(Some(source))
         .parse(Stream::from_iter(srclen..srclen + 1, tokens.into_iter()))
-        .map_err(|errors| {
-            errors
-                .into_iter()
-                .map(|err| {
-                    let span = err.span();
-                    Spanned::new(Error::SyntaxError(err), span)
-                })
-                .collect()
-        })
+        .map_err(syntax_errors)
 }
 
 pub fn parse_tokens(tokens: Vec<Token>) -> Result<Spanned<Expression>, Vec<Spanned<Error>>> {
     let srclen = tokens.len();
-    create_parser()
-        .parse(Stream::from_iter(
-            srclen..srclen + 1,
-            tokens
-                .into_iter()
-                .enumerate()
-                .map(|(i, val)| (val, i..i + 1)),
-        ))
-        .map_err(|errors| {
-            errors
-                .into_iter()
-                .map(|err| {
-                    let span = err.span();
-                    Spanned::new(Error::SyntaxError(err), span)
-                })
-                .collect()
-        })
+    let tokens: Vec<(Token, Range<usize>)> = tokens
+        .into_iter()
+        .enumerate()
+        .map(|(i, val)| (val, i..i + 1))
+        .collect();
+    let errors = check_string_literals(&tokens, None);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    create_parser(None)
+        .parse(Stream::from_iter(srclen..srclen + 1, tokens.into_iter()))
+        .map_err(syntax_errors)
 }
 
-pub fn get_tokens(source: &str) -> Vec<Result<Token, ()>> {
+pub fn get_tokens(source: &str) -> Vec<Result<Token, LexingError>> {
     let mut lexer = Token::lexer(source);
     let mut tokens = vec![];
     while let Some(token) = lexer.next() {
@@ -74,11 +78,227 @@ pub fn get_tokens(source: &str) -> Vec<Result<Token, ()>> {
     tokens
 }
 
-fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Token>> {
+/// Reports errors in embedded expressions of string literals.
+/// The parser itself would only report the literal as an unexpected token.
+fn check_string_literals(
+    tokens: &[(Token, Range<usize>)],
+    source: Option<&str>,
+) -> Vec<Spanned<Error>> {
+    tokens
+        .iter()
+        .filter_map(|(token, span)| match token {
+            Token::String(text) => string_literal(text.to_owned(), span.clone(), source).err(),
+            _ => None,
+        })
+        .map(|err| syntax_error(*err))
+        .collect()
+}
+
+/// Converts errors of the parser, alternatives of the grammar can report an error twice
+fn syntax_errors(errors: Vec<Simple<Token>>) -> Vec<Spanned<Error>> {
+    let mut errors = errors.into_iter().map(syntax_error).collect::<Vec<_>>();
+    // The error that knows the construct is kept
+    errors.dedup_by(|later, earlier| {
+        if later.span != earlier.span {
+            return false;
+        }
+        if let Error::UnexpectedToken { context: None, .. } = earlier.item {
+            std::mem::swap(later, earlier);
+        }
+        true
+    });
+    errors
+}
+
+/// Converts an error of the parser to a structured error of the language
+fn syntax_error(err: Simple<Token>) -> Spanned<Error> {
+    let span = err.span();
+    let error = match err.reason() {
+        SimpleReason::Custom(text) if text == INVALID_TOKEN => Error::InvalidToken,
+        SimpleReason::Custom(text) if text == UNMATCHED_BRACE => Error::UnmatchedBrace,
+        SimpleReason::Custom(text) if text == EXPECTED_ONE_EXPRESSION => {
+            Error::ExpectedOneExpression
+        }
+        SimpleReason::Custom(text) if text == EXPECTED_VARIABLE => Error::ExpectedVariable,
+        SimpleReason::Custom(text) => match text.strip_prefix(INVALID_NUMBER) {
+            Some(literal) => Error::InvalidNumber(literal.to_owned()),
+            None => Error::InvalidSyntax(text.to_owned()),
+        },
+        SimpleReason::Unclosed { delimiter, .. } => Error::UnclosedDelimiter(delimiter.clone()),
+        SimpleReason::Unexpected => Error::UnexpectedToken {
+            found: err.found().cloned(),
+            expected: err.expected().cloned().collect(),
+            context: err.label().and_then(SyntaxContext::from_label),
+        },
+    };
+    Spanned::new(error, span)
+}
+
+/// Segment of a member access chain
+enum Accessor {
+    /// Field name, true if it is a function name
+    Member(Spanned<String>, bool),
+    Index(Spanned<Expression>, Range<usize>),
+    Call(Vec<Spanned<Expression>>, Range<usize>),
+}
+
+/// Part of a string literal
+enum StringPart {
+    Text(String),
+    /// Range of an embedded expression in the string
+    Expression(Range<usize>),
+}
+
+/// Splits a string into text and `{expression}` parts, `{{` and `}}` stand for literal braces.
+/// Returns the range of an unmatched brace on error.
+fn split_interpolation(text: &str) -> Result<Vec<StringPart>, Range<usize>> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '{' if chars.next_if(|(_, c)| *c == '{').is_some() => literal.push('{'),
+            '}' if chars.next_if(|(_, c)| *c == '}').is_some() => literal.push('}'),
+            '{' => {
+                // Braces inside of the expression (objects, blocks) have to be balanced,
+                // the ones in its string literals are not counted
+                let mut depth = 1;
+                let mut quoted = false;
+                let mut escaped = false;
+                let end = chars.find_map(|(i, c)| {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' if quoted => escaped = true,
+                        '"' => quoted = !quoted,
+                        _ if quoted => {}
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    (depth == 0).then_some(i)
+                });
+                let end = end.ok_or(start..text.len())?;
+                if !literal.is_empty() {
+                    parts.push(StringPart::Text(std::mem::take(&mut literal)));
+                }
+                parts.push(StringPart::Expression(start + 1..end));
+            }
+            '}' => return Err(start..start + 1),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() || parts.is_empty() {
+        parts.push(StringPart::Text(literal));
+    }
+    Ok(parts)
+}
+
+/// Creates the expression of a string literal with the given (decoded) text.
+/// `source` is the program source if the literal was lexed from it, otherwise
+/// the literal is an editor icon and everything in it gets the span of the icon.
+fn string_literal(
+    text: String,
+    span: Range<usize>,
+    source: Option<&str>,
+) -> Result<Expression, Box<Simple<Token>>> {
+    if !text.contains(['{', '}']) {
+        return Ok(Expression::String(text));
+    }
+    // Escape sequences make the literal in the source longer than its text
+    let offsets = source
+        .and_then(|source| unescape_string_offsets(&source[span.start + 1..span.end - 1]).ok())
+        .map(|(_text, offsets)| offsets);
+    let map_span = |range: Range<usize>| match &offsets {
+        Some(offsets) => span.start + 1 + offsets[range.start]..span.start + 1 + offsets[range.end],
+        None => span.clone(),
+    };
+
+    let parts = split_interpolation(&text)
+        .map_err(|range| Box::new(Simple::custom(map_span(range), UNMATCHED_BRACE)))?;
+    if let [StringPart::Text(text)] = &parts[..] {
+        return Ok(Expression::String(text.to_owned()));
+    }
+    let mut expressions = vec![];
+    for part in parts {
+        expressions.push(match part {
+            StringPart::Text(text) => Spanned::new(Expression::String(text), span.clone()),
+            StringPart::Expression(range) => {
+                parse_embedded(&text[range.clone()], map_span(range.clone()), |inner| {
+                    map_span(range.start + inner.start..range.start + inner.end)
+                })?
+            }
+        });
+    }
+    Ok(Expression::Interpolation(expressions))
+}
+
+/// Parses a single expression embedded in a string literal.
+/// `map_span` converts spans of `text` to spans of the program.
+fn parse_embedded(
+    text: &str,
+    span: Range<usize>,
+    map_span: impl Fn(Range<usize>) -> Range<usize>,
+) -> Result<Spanned<Expression>, Box<Simple<Token>>> {
+    let mut tokens = vec![];
+    for (token, token_span) in Token::lexer(text).spanned() {
+        match token {
+            Ok(token) => tokens.push((token, map_span(token_span))),
+            Err(_) => {
+                return Err(Box::new(Simple::custom(
+                    map_span(token_span),
+                    INVALID_TOKEN,
+                )))
+            }
+        }
+    }
+    let ast = create_parser(None)
+        .parse(Stream::from_iter(
+            span.end..span.end + 1,
+            tokens.into_iter(),
+        ))
+        .map_err(|mut errors| Box::new(errors.remove(0)))?;
+    match ast.item {
+        Expression::Block(mut items) if items.len() == 1 => Ok(items.remove(0)),
+        _ => Err(Box::new(Simple::custom(span, EXPECTED_ONE_EXPRESSION))),
+    }
+}
+
+fn create_parser<'a>(
+    source: Option<&'a str>,
+) -> impl Parser<Token, Spanned<Expression>, Error = Simple<Token>> + 'a {
     let padding = (just(Token::Newline).or(just(Token::Space))).repeated();
 
+    // Braces are skipped in pairs, so that a skipped statement does not end its block
+    let braces = recursive(|braces| {
+        none_of([Token::LeftCurly, Token::RightCurly])
+            .ignored()
+            .or(braces)
+            .repeated()
+            .delimited_by(just(Token::LeftCurly), just(Token::RightCurly))
+            .ignored()
+    });
+    // A statement that cannot be parsed is skipped up to the end of its line or block
+    // and reported, so that the rest of the program is checked too.
+    // `ends` are the tokens that end the sequence of statements.
+    let statement = |expr: Recursive<'a, Token, Spanned<Expression>, Simple<Token>>,
+                     ends: Vec<Token>| {
+        let mut stops = vec![Token::Newline, Token::Space, Token::LeftCurly];
+        stops.extend(ends.iter().cloned());
+        let skipped = braces
+            .clone()
+            .or(just(Token::LeftCurly).ignored())
+            .or(none_of(stops).ignored())
+            .repeated()
+            .at_least(1)
+            .map_with_span(|_, span| Spanned::new(Expression::None, span));
+        padding
+            .clone()
+            .ignore_then(none_of(ends).rewind())
+            .ignore_then(expr.recover_with(skip_parser(skipped)))
+    };
+
     let expression = recursive(|expr| {
-        let block = (expr.clone().repeated())
+        let block = (statement(expr.clone(), vec![Token::RightCurly]).repeated())
             .delimited_by(
                 padding
                     .clone()
@@ -88,17 +308,19 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
             )
             .map(Expression::Block)
             .map_with_span(Spanned::new);
-        let literal = filter_map(|span, token: Token| match token {
+        let literal = filter_map(move |span, token: Token| match token {
             Token::Integer(x) => Ok(Expression::Int(x)),
-            Token::String(x) => Ok(Expression::String(x)),
+            Token::String(x) => string_literal(x, span, source).map_err(|err| *err),
             Token::Image(x) => Ok(Expression::Image(x)),
             Token::Tilemap(x) => Ok(Expression::Tilemap(x)),
             Token::Key(x) => Ok(Expression::Key(x)),
+            Token::True => Ok(Expression::Bool(true)),
+            Token::False => Ok(Expression::Bool(false)),
             Token::Float(x) => x
                 .parse()
-                .map_err(|err: ParseFloatError| Simple::custom(span, err.to_string()))
+                .map_err(|_: ParseFloatError| Simple::custom(span, format!("{INVALID_NUMBER}{x}")))
                 .map(|value| Expression::Float(value)),
-            _ => Err(Simple::custom(span, "Not a literal")),
+            token => Err(Simple::expected_input_found(span, [], Some(token))),
         })
         .map_with_span(Spanned::new);
 
@@ -117,102 +339,137 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
             })
             .map_with_span(Spanned::new);
 
+        let listdef = expr
+            .clone()
+            .separated_by(just(Token::Comma))
+            .delimited_by(just(Token::LeftSquare).then(padding.clone()), just(Token::RightSquare))
+            .map(|items| Expression::ListDef { items })
+            .map_with_span(Spanned::new);
+
         let var_name = select! {Token::Variable(x) => x}.map_with_span(Spanned::new);
         let func_name = select! {Token::Function(x) => x}.map_with_span(Spanned::new);
 
-        let var = (var_name
-            .clone()
-            .then_ignore(just(Token::Dot))
-            .then(var_name)
-            .map(|(parent, key)| {
-                let p_ex = Spanned::new(
-                    Expression::Variable {
-                        parent: None,
-                        name: parent.item,
-                    },
-                    parent.span,
-                );
-                Expression::Variable {
-                    parent: Some(Box::new(p_ex)),
-                    name: key.item,
-                }
-            }))
-        .or(var_name.map(|v| Expression::Variable {
-            parent: None,
-            name: v.item,
-        }))
-        .map_with_span(Spanned::new);
-        let func = (var_name
-            .clone()
-            .then_ignore(just(Token::Dot))
-            .then(func_name)
-            .map(|(parent, key)| {
-                let p_ex = Spanned::new(
-                    Expression::Variable {
-                        parent: None,
-                        name: parent.item,
-                    },
-                    parent.span,
-                );
-                Expression::Variable {
-                    parent: Some(Box::new(p_ex)),
-                    name: key.item,
-                }
-            }))
-        .or(func_name.map(|v| Expression::Variable {
-            parent: None,
-            name: v.item,
-        }))
-        .map_with_span(Spanned::new);
-
-        // Expressions
-        let call = func
-            .clone()
-            .then(
-                expr.clone()
-                    .separated_by(just(Token::Comma))
-                    .allow_leading() //TODO: Remove
-                    .delimited_by(just(Token::LeftParent), just(Token::RightParent)),
+        // Variables and function names can be followed by member access, indexing and calls
+        // in any order (`$game.$turtle.go(1)`, `$list[0].$x`, `$obj[$key]`)
+        let accessor = just(Token::Dot)
+            .ignore_then(
+                var_name
+                    .map(|name| (name, false))
+                    .or(func_name.map(|name| (name, true))),
             )
-            .map(|(ident, args)| Expression::Call {
-                expr: Box::new(ident),
-                args: args,
+            .map(|(name, is_func)| Accessor::Member(name, is_func))
+            .or(expr
+                .clone()
+                .delimited_by(just(Token::LeftSquare), just(Token::RightSquare))
+                .map_with_span(Accessor::Index))
+            .or(expr
+                .clone()
+                .separated_by(just(Token::Comma))
+                .allow_leading() //TODO: Remove
+                .delimited_by(just(Token::LeftParent), just(Token::RightParent))
+                .labelled(SyntaxContext::FunctionArgs.label())
+                .map_with_span(Accessor::Call));
+        // The flag tells whether the chain ends with a function name
+        let chain = var_name
+            .map(|name| (name, false))
+            .or(func_name.map(|name| (name, true)))
+            .map(|(name, is_func)| {
+                let expr = Expression::Variable {
+                    parent: None,
+                    name: name.item,
+                };
+                (Spanned::new(expr, name.span), is_func)
             })
-            .map_with_span(Spanned::new);
-
-        let shortcall_param = literal.clone().or(var.clone());
-        let shortcall = func
+            .then(accessor.repeated())
+            .foldl(|(target, _), accessor| {
+                let start = target.span.start;
+                match accessor {
+                    Accessor::Member(name, is_func) => {
+                        let expr = Expression::Variable {
+                            parent: Some(Box::new(target)),
+                            name: name.item,
+                        };
+                        (Spanned::new(expr, start..name.span.end), is_func)
+                    }
+                    Accessor::Index(index, span) => {
+                        let expr = Expression::Index {
+                            expr: Box::new(target),
+                            index: Box::new(index),
+                        };
+                        (Spanned::new(expr, start..span.end), false)
+                    }
+                    Accessor::Call(args, span) => {
+                        let expr = Expression::Call {
+                            expr: Box::new(target),
+                            args,
+                        };
+                        (Spanned::new(expr, start..span.end), false)
+                    }
+                }
+            });
+        let var = chain
             .clone()
-            .then(
-                shortcall_param.clone().repeated(), /*.separated_by(just(Token::Comma))*/
-            )
-            .map(|(ident, args)| Expression::Call {
-                expr: Box::new(ident),
-                args: args,
-            })
-            .or(func.clone().map(|ident| Expression::Call {
-                expr: Box::new(ident),
-                args: vec![],
-            }))
-            .map_with_span(Spanned::new);
+            .try_map(|(target, is_func), span| match is_func {
+                false => Ok(target),
+                true => Err(Simple::custom(span, EXPECTED_VARIABLE)),
+            });
 
-        let assignment = var
-            .clone()
-            .then_ignore(just(Token::Assignment))
-            .then(expr.clone())
-            .map(|(expr, value)| Expression::Assignment {
-                expr: Box::new(expr),
-                value: Box::new(value),
+        // Function names without parentheses take the following literals and variables as arguments,
+        // other chains can be assigned to
+        let shortcall_param = literal.or(var.clone());
+        let assignment_op = just(Token::Assignment)
+            .to(None)
+            .or(just(Token::PlusAssignment).to(Some(AssignmentOperator::Addition)))
+            .or(just(Token::MinusAssignment).to(Some(AssignmentOperator::Subtraction)))
+            .or(just(Token::StarAssignment).to(Some(AssignmentOperator::Multiply)))
+            .or(just(Token::SlashAssignment).to(Some(AssignmentOperator::Division)));
+        let assigned_value = expr.clone();
+        let access = chain
+            .then_with(move |(target, is_func)| match is_func {
+                true => shortcall_param
+                    .clone()
+                    .repeated()
+                    .map(move |args| Expression::Call {
+                        expr: Box::new(target.clone()),
+                        args,
+                    })
+                    .boxed(),
+                false => assignment_op
+                    .clone()
+                    .then(assigned_value.clone())
+                    .or_not()
+                    .map(move |assignment| match assignment {
+                        Some((None, value)) => Expression::Assignment {
+                            expr: Box::new(target.clone()),
+                            value: Box::new(value),
+                        },
+                        Some((Some(op), value)) => Expression::CompoundAssignment {
+                            expr: Box::new(target.clone()),
+                            op,
+                            value: Box::new(value),
+                        },
+                        None => target.item.clone(),
+                    })
+                    .boxed(),
             })
             .map_with_span(Spanned::new);
 
         // Keywords
         let condition = just(Token::If)
+            .then(expr.clone().labelled(SyntaxContext::IfCondition.label()))
             .then(expr.clone())
-            .then(expr.clone())
-            .map(|((_token, cond), body)| Expression::If {
+            .then(
+                // The else keyword may be placed on the line following the body
+                padding
+                    .clone()
+                    .ignore_then(just(Token::Else))
+                    .ignore_then(expr.clone())
+                    .or_not(),
+            )
+            .map(|(((_token, cond), body), else_body)| Expression::If {
                 cond: Box::new(cond),
                 body: Box::new(body),
+                else_body: else_body.map(Box::new),
             })
             .map_with_span(Spanned::new);
 
@@ -226,9 +483,30 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
             }))
             .map_with_span(Spanned::new);
         let break_expr = just(Token::Break)
-            .map_err(|e: Simple<Token>| Simple::custom(e.span(), "Not break"))
+            // Keywords are not listed as expected tokens, there are too many of them
+            .map_err(|e: Simple<Token>| {
+                Simple::expected_input_found(e.span(), [], e.found().cloned())
+            })
             .map(|_token| Expression::Break)
             .map_with_span(Spanned::new);
+        let scoped_assignment = just(Token::Local)
+            .to(VariableScope::Local)
+            .or(just(Token::Global).to(VariableScope::Global))
+            .then(var_name)
+            .then_ignore(just(Token::Assignment))
+            .then(expr.clone())
+            .map(|((scope, name), value)| Expression::ScopedAssignment {
+                scope,
+                name: name.item,
+                value: Box::new(value),
+            })
+            .map_with_span(Spanned::new);
+        let continue_expr = just(Token::Continue)
+            .map_err(|e: Simple<Token>| {
+                Simple::expected_input_found(e.span(), [], e.found().cloned())
+            })
+            .map(|_token| Expression::Continue)
+            .map_with_span(Spanned::new);
 
         // Loops
         let loop_finite = just(Token::Loop)
@@ -245,13 +523,30 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
                 body: Box::new(body),
             })
             .map_with_span(Spanned::new);
+        let loop_for_each = just(Token::For)
+            .then(
+                var_name
+                    .then_ignore(just(Token::In))
+                    .then(expr.clone())
+                    .labelled(SyntaxContext::ForHeader.label()),
+            )
+            .then(block.clone())
+            .map(|((_token, (var, collection)), body)| Expression::ForEach {
+                var: var.item,
+                collection: Box::new(collection),
+                body: Box::new(body),
+            })
+            .map_with_span(Spanned::new);
         let loop_for = just(Token::For)
-            .then(var_name.clone())
-            .then(expr.clone())
-            .then(expr.clone())
-            .then(expr.clone())
+            .then(
+                var_name
+                    .then(expr.clone())
+                    .then(expr.clone())
+                    .then(expr.clone())
+                    .labelled(SyntaxContext::ForHeader.label()),
+            )
             .then(block.clone())
-            .map(|(((((_token, var), start), end), step), body)| {
+            .map(|((_token, (((var, start), end), step)), body)| {
                 return Expression::For {
                     var: var.item,
                     start: Box::new(start),
@@ -261,11 +556,14 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
                 };
             })
             .or(just(Token::For)
-                .then(var_name.clone())
-                .then(expr.clone())
-                .then(expr.clone())
+                .then(
+                    var_name
+                        .then(expr.clone())
+                        .then(expr.clone())
+                        .labelled(SyntaxContext::ForHeader.label()),
+                )
                 .then(block.clone())
-                .map(|((((_token, var), start), end), body)| {
+                .map(|((_token, ((var, start), end)), body)| {
                     return Expression::For {
                         var: var.item,
                         start: Box::new(start),
@@ -285,12 +583,14 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
             .map_with_span(Spanned::new);
 
         // Structure
+        // Functions without a name are anonymous function values
         let fndef = just(Token::FnDef)
-            .then(select! {Token::Function(x) => x})
+            .then((select! {Token::Function(x) => x}).or_not())
             .then(
                 (select! {Token::Variable(x) => x})
                     .separated_by(just(Token::Comma))
-                    .delimited_by(just(Token::LeftParent), just(Token::RightParent)),
+                    .delimited_by(just(Token::LeftParent), just(Token::RightParent))
+                    .labelled(SyntaxContext::FunctionArgs.label()),
             )
             .then(expr.clone())
             .map(|(((_token, name), args), body)| Expression::FnDef {
@@ -305,40 +605,71 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
             .or(expr
                 .clone()
                 .delimited_by(just(Token::LeftParent), just(Token::RightParent)))
-            .or(call)
-            .or(assignment)
+            .or(access)
             .or(condition)
             .or(ret)
             .or(break_expr)
+            .or(continue_expr)
+            .or(scoped_assignment)
             .or(loop_finite)
             .or(loop_infinite)
+            .or(loop_for_each)
             .or(loop_for)
             .or(loop_while)
             .or(fndef)
-            .or(shortcall)
-            .or(var)
-            .or(func)
-            .or(objdef);
+            .or(objdef)
+            .or(listdef);
         let atom = padding
             .clone()
             .ignore_then(atom)
             .then_ignore(padding.clone());
 
         // Operators
+        let negation = |op: Spanned<Token>, rhs: Spanned<Expression>| {
+            let end = rhs.span.end;
+            Spanned::new(
+                Expression::Negation(Box::new(rhs)),
+                Range {
+                    start: op.span.start,
+                    end,
+                },
+            )
+        };
+
+        // Power is right associative and binds tighter than negation on its left (-2 ** 2 == -4)
+        let power = atom
+            .clone()
+            .then(
+                just(Token::DoubleStar)
+                    .ignore_then(
+                        just(Token::Minus)
+                            .map_with_span(Spanned::new)
+                            .repeated()
+                            .then(atom.clone())
+                            .foldr(negation),
+                    )
+                    .repeated(),
+            )
+            .map(|(lhs, rhs)| {
+                let mut operands = vec![lhs];
+                operands.extend(rhs);
+                let mut result = operands.pop().unwrap();
+                while let Some(lhs) = operands.pop() {
+                    let start = lhs.span.start;
+                    let end = result.span.end;
+                    result = Spanned::new(
+                        Expression::Power(Box::new(lhs), Box::new(result)),
+                        Range { start, end },
+                    );
+                }
+                result
+            });
+
         let unary = just(Token::Minus)
             .map_with_span(Spanned::new)
             .repeated()
-            .then(atom.clone())
-            .foldr(|op, rhs| {
-                let end = rhs.span.end;
-                Spanned::new(
-                    Expression::Negation(Box::new(rhs)),
-                    Range {
-                        start: op.span.start,
-                        end: end,
-                    },
-                )
-            });
+            .then(power)
+            .foldr(negation);
 
         let product = unary
             .clone()
@@ -349,6 +680,12 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
                     .or(just(Token::Slash)
                         .to(Expression::Division as fn(_, _) -> _)
                         .map_with_span(Spanned::new))
+                    .or(just(Token::DoubleSlash)
+                        .to(Expression::IntDivision as fn(_, _) -> _)
+                        .map_with_span(Spanned::new))
+                    .or(just(Token::Percent)
+                        .to(Expression::Modulo as fn(_, _) -> _)
+                        .map_with_span(Spanned::new))
                     .then(unary)
                     .repeated(),
             )
@@ -424,11 +761,63 @@ fn create_parser() -> impl Parser<Token, Spanned<Expression>, Error = Simple<Tok
                 )
             });
 
-        eq.or(block)
+        let not = just(Token::Not)
+            .map_with_span(Spanned::new)
+            .repeated()
+            .then(eq)
+            .foldr(|op, rhs| {
+                let end = rhs.span.end;
+                Spanned::new(
+                    Expression::Not(Box::new(rhs)),
+                    Range {
+                        start: op.span.start,
+                        end,
+                    },
+                )
+            });
+
+        let and = not
+            .clone()
+            .then(
+                just(Token::And)
+                    .to(Expression::And as fn(_, _) -> _)
+                    .map_with_span(Spanned::new)
+                    .then(not)
+                    .repeated(),
+            )
+            .foldl(|lhs, (op, rhs)| {
+                let start = lhs.span.start;
+                let end = rhs.span.end;
+                Spanned::new(
+                    (op.item)(Box::new(lhs), Box::new(rhs)),
+                    Range { start, end },
+                )
+            });
+
+        let or = and
+            .clone()
+            .then(
+                just(Token::Or)
+                    .to(Expression::Or as fn(_, _) -> _)
+                    .map_with_span(Spanned::new)
+                    .then(and)
+                    .repeated(),
+            )
+            .foldl(|lhs, (op, rhs)| {
+                let start = lhs.span.start;
+                let end = rhs.span.end;
+                Spanned::new(
+                    (op.item)(Box::new(lhs), Box::new(rhs)),
+                    Range { start, end },
+                )
+            });
+
+        or.or(block)
     });
     padding.clone().ignore_then(
-        expression
+        statement(expression, vec![])
             .repeated()
+            .then_ignore(padding)
             .map(Expression::Block)
             .map_with_span(|tok, span| Spanned::new(tok, span))
             .then_ignore(end()),
@@ -0,0 +1,269 @@
+//! Static checks that find mistakes before the program runs.
+//!
+//! Names are resolved like at runtime: a function sees its parameters, variables it creates,
+//! variables of the functions it is defined in and the global scope. A name that none of them
+//! can ever create is reported as a warning, because the read may never run, e.g. in an operand
+//! skipped by `and`/`or`. Errors are reported only for code that always fails when it runs.
+
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    ast::{Expression, Spanned, VariableScope},
+    diagnostics::{Diagnostic, Severity},
+    error::{Error, RuntimeError},
+};
+
+/// Mistake found in a program
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub error: Spanned<Error>,
+}
+
+impl Problem {
+    pub fn diagnostic(&self) -> Diagnostic<'_> {
+        Diagnostic::from(&self.error).with_severity(self.severity)
+    }
+}
+
+/// What a scope knows about a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binding {
+    /// Script function with the number of its parameters
+    Function(usize),
+    Other,
+}
+
+type Scope = HashMap<String, Binding>;
+
+/// Checks the program, `globals` are names defined before it runs, e.g. by libraries
+pub fn resolve<'a>(
+    program: &Spanned<Expression>,
+    globals: impl IntoIterator<Item = &'a str>,
+) -> Vec<Problem> {
+    let mut global: Scope = globals
+        .into_iter()
+        .map(|name| (name.to_owned(), Binding::Other))
+        .collect();
+    collect_definitions(program, &mut global);
+    collect_globals(program, &mut global);
+    let mut resolver = Resolver {
+        scopes: vec![global],
+        loops: 0,
+        problems: vec![],
+    };
+    resolver.expression(program);
+    resolver.problems
+}
+
+struct Resolver {
+    /// Scopes visible from the checked code, the innermost is the last one
+    scopes: Vec<Scope>,
+    /// Number of loops around the checked code in the current function
+    loops: usize,
+    problems: Vec<Problem>,
+}
+
+impl Resolver {
+    fn report(&mut self, severity: Severity, error: Error, span: Range<usize>) {
+        self.problems.push(Problem {
+            severity,
+            error: Spanned::new(error, span),
+        });
+    }
+
+    fn variable(&mut self, name: &str, span: Range<usize>) {
+        if !self.scopes.iter().any(|scope| scope.contains_key(name)) {
+            let err = RuntimeError::InvalidIdentifier(name.to_owned());
+            self.report(Severity::Warning, Error::RuntimeError(err), span);
+        }
+    }
+
+    /// Number of parameters of the called function if every visible definition agrees on it
+    fn params(&self, name: &str) -> Option<usize> {
+        let mut bindings = self.scopes.iter().filter_map(|scope| scope.get(name));
+        let first = bindings.next()?;
+        match first {
+            Binding::Function(params) if bindings.all(|binding| binding == first) => Some(*params),
+            _ => None,
+        }
+    }
+
+    fn loop_body(&mut self, body: &Spanned<Expression>) {
+        self.loops += 1;
+        self.expression(body);
+        self.loops -= 1;
+    }
+
+    fn expression(&mut self, expression: &Spanned<Expression>) {
+        match &expression.item {
+            Expression::Variable { parent: None, name } => {
+                self.variable(name, expression.span.to_owned())
+            }
+            Expression::Call { expr, args } => {
+                self.expression(expr);
+                if let Expression::Variable { parent: None, name } = &expr.item {
+                    match self.params(name) {
+                        Some(params) if params != args.len() => {
+                            let err = RuntimeError::InvalidArgCount(args.len(), params);
+                            self.report(
+                                Severity::Error,
+                                Error::RuntimeError(err),
+                                expression.span.to_owned(),
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::Assignment { expr, value } => {
+                self.expression(value);
+                // Plain assignments create variables
+                match &expr.item {
+                    Expression::Variable { parent: None, .. } => {}
+                    _ => self.expression(expr),
+                }
+            }
+            Expression::FnDef { args, body, .. } => {
+                let mut scope: Scope = args
+                    .iter()
+                    .map(|arg| (arg.to_owned(), Binding::Other))
+                    .collect();
+                collect_definitions(body, &mut scope);
+                self.scopes.push(scope);
+                // Loops around the definition do not belong to the function
+                let loops = std::mem::replace(&mut self.loops, 0);
+                self.expression(body);
+                self.loops = loops;
+                self.scopes.pop();
+            }
+            Expression::Break | Expression::Continue if self.loops == 0 => {
+                let err = match expression.item {
+                    Expression::Break => Error::BreakOutsideLoop,
+                    _ => Error::ContinueOutsideLoop,
+                };
+                self.report(Severity::Error, err, expression.span.to_owned());
+            }
+            Expression::Block(block) => {
+                for (i, expr) in block.iter().enumerate() {
+                    self.expression(expr);
+                    let jumps = matches!(
+                        expr.item,
+                        Expression::Return { .. } | Expression::Break | Expression::Continue
+                    );
+                    if let (true, Some(next), Some(last)) = (jumps, block.get(i + 1), block.last())
+                    {
+                        self.report(
+                            Severity::Warning,
+                            Error::UnreachableCode,
+                            next.span.start..last.span.end,
+                        );
+                    }
+                }
+            }
+            Expression::LoopInfinite { body } => self.loop_body(body),
+            Expression::LoopFinite { iters, body } => {
+                self.expression(iters);
+                self.loop_body(body);
+            }
+            Expression::For {
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => {
+                self.expression(start);
+                self.expression(end);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+                self.loop_body(body);
+            }
+            Expression::ForEach {
+                collection, body, ..
+            } => {
+                self.expression(collection);
+                self.loop_body(body);
+            }
+            Expression::While { cond, body } => {
+                self.expression(cond);
+                self.loop_body(body);
+            }
+            item => {
+                for child in item.children() {
+                    self.expression(child);
+                }
+            }
+        }
+    }
+}
+
+/// Adds a definition, names defined in different ways are not known to be functions
+fn define(scope: &mut Scope, name: &str, binding: Binding) {
+    scope
+        .entry(name.to_owned())
+        .and_modify(|known| {
+            if *known != binding {
+                *known = Binding::Other
+            }
+        })
+        .or_insert(binding);
+}
+
+/// Collects names that the code of a function can create in its own scope
+fn collect_definitions(expression: &Spanned<Expression>, scope: &mut Scope) {
+    match &expression.item {
+        Expression::Assignment { expr, value } => {
+            if let Expression::Variable { parent: None, name } = &expr.item {
+                define(scope, name, binding(value));
+            }
+        }
+        Expression::ScopedAssignment {
+            scope: VariableScope::Local,
+            name,
+            value,
+        } => define(scope, name, binding(value)),
+        Expression::For { var, .. } | Expression::ForEach { var, .. } => {
+            define(scope, var, Binding::Other)
+        }
+        Expression::FnDef { name, args, .. } => {
+            if let Some(name) = name {
+                define(scope, name, Binding::Function(args.len()));
+            }
+            // Nested functions have their own scopes
+            return;
+        }
+        _ => {}
+    }
+    for child in expression.item.children() {
+        collect_definitions(child, scope);
+    }
+}
+
+/// Collects `global` assignments from the whole program including functions
+fn collect_globals(expression: &Spanned<Expression>, scope: &mut Scope) {
+    if let Expression::ScopedAssignment {
+        scope: VariableScope::Global,
+        name,
+        value,
+    } = &expression.item
+    {
+        define(scope, name, binding(value));
+    }
+    for child in expression.item.children() {
+        collect_globals(child, scope);
+    }
+}
+
+fn binding(value: &Spanned<Expression>) -> Binding {
+    match &value.item {
+        Expression::FnDef {
+            name: None, args, ..
+        } => Binding::Function(args.len()),
+        _ => Binding::Other,
+    }
+}
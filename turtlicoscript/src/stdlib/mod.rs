@@ -3,7 +3,7 @@ use std::io::Write;
 
 use crate::error::RuntimeError;
 use crate::interpreter::Scope;
-use crate::value::{Value, NativeFuncArgs, NativeFuncReturn, Library, LibraryContext, NativeFuncCtxArg, FuncThisObject};
+use crate::value::{list_index, Value, NativeFuncArgs, NativeFuncReturn, Library, LibraryContext, NativeFuncCtxArg, FuncThisObject};
 use crate::{funcmap, check_argc};
 use checkargs::check_args;
 use rand::Rng;
@@ -28,7 +28,12 @@ pub fn init_library() -> Library {
         int,
         float,
         string,
-        random
+        random,
+        len,
+        push,
+        pop,
+        insert,
+        remove
     });
     let ctx = Context {};
     Library {
@@ -112,4 +117,47 @@ pub fn random(_ctx: &mut NativeFuncCtxArg, _this: FuncThisObject, mut args: Nati
             Err(crate::error::RuntimeError::InvalidArgCount(args.len(), 2))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Returns the number of items in a list, characters in a string or fields of an object
+pub fn len(_ctx: &mut NativeFuncCtxArg, _this: FuncThisObject, args: NativeFuncArgs) -> NativeFuncReturn {
+    check_argc!(args, 1);
+    match &args[0] {
+        Value::List(list) => Ok(list.borrow().len().into()),
+        Value::String(string) => Ok(string.chars().count().into()),
+        Value::Object(object) => Ok(object.borrow().fields.len().into()),
+        _ => Err(RuntimeError::InvalidArgType(0))
+    }
+}
+
+/// Appends an item to the end of a list
+#[check_args(List, Other)]
+pub fn push(_ctx: &mut NativeFuncCtxArg, _this: FuncThisObject, mut args: NativeFuncArgs) -> NativeFuncReturn {
+    arg0.borrow_mut().push(args.remove(1));
+    Ok(Value::None)
+}
+
+/// Removes the last item of a list and returns it
+#[check_args(List)]
+pub fn pop(_ctx: &mut NativeFuncCtxArg, _this: FuncThisObject, args: NativeFuncArgs) -> NativeFuncReturn {
+    let value = arg0.borrow_mut().pop();
+    value.ok_or(RuntimeError::EmptyList)
+}
+
+/// Inserts an item at the index, shifting all items after it
+#[check_args(List, Int, Other)]
+pub fn insert(_ctx: &mut NativeFuncCtxArg, _this: FuncThisObject, mut args: NativeFuncArgs) -> NativeFuncReturn {
+    let mut list = arg0.borrow_mut();
+    // Inserting right after the last item is allowed
+    let index = list_index(arg1, list.len() + 1)?;
+    list.insert(index, args.remove(2));
+    Ok(Value::None)
+}
+
+/// Removes an item at the index and returns it
+#[check_args(List, Int)]
+pub fn remove(_ctx: &mut NativeFuncCtxArg, _this: FuncThisObject, args: NativeFuncArgs) -> NativeFuncReturn {
+    let mut list = arg0.borrow_mut();
+    let index = list_index(arg1, list.len())?;
+    Ok(list.remove(index))
+}
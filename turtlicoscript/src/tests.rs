@@ -1,9 +1,15 @@
 
 #[cfg(test)]
 mod test_examples {
-    use std::{env, path, fs};
+    use std::{env, path, fs, rc::Rc, time::Duration};
 
-    use crate::{parser, interpreter};
+
+    use crate::{ast::{Expression, Spanned}, diagnostics::{self, Diagnostic, Format, Severity}, error::{Error, RuntimeError, TypeErrorKind}, interpreter::{self, Engine}, locale::Language, optimizer, parser, tokens::{self, Token}, value::Value};
+
+    #[test]
+    fn arithmetic() {
+        test_example_file("arithmetic.tcsf", "1_15_3_-4_1024_512_-4_0.5_1.5_3_2_4_eoeoeo_0");
+    }
 
     #[test]
     fn binops() {
@@ -15,6 +21,11 @@ mod test_examples {
         test_example_file("for.tcsf", "0_1_2_3_4_5_6_7_8_9_10_0_2_4_6_8_10_");
     }
 
+    #[test]
+    fn closures() {
+        test_example_file("closures.tcsf", "3_12_3_12_21_global_55_true");
+    }
+
     #[test]
     fn funcs() {
         test_example_file("funcs.tcsf", "7_5_3_");
@@ -25,18 +36,511 @@ mod test_examples {
         test_example_file("objects.tcsf", "1_text_value_text_inserted");
     }
 
+    #[test]
+    fn object_printing() {
+        test_example_file(
+            "object_print.tcsf",
+            "{\"hello\": 1, \"go\": \"went\", \"numbers\": {2: \"two\", 1: \"one\"}, \"list\": [1, \"a\"], \"self\": {...}}_[1, [...], {\"list\": [...]}]",
+        );
+    }
+
+    #[test]
+    fn members() {
+        test_example_file("members.tcsf", "5_25_2_7_onethree_34_810_12_6");
+        test_example_file_tokens("members.tcsf", "5_25_2_7_onethree_34_810_12_6");
+        for (src, name, span) in [
+            ("$a = { \"b\": {} }\n$a.$b.$c", "c", 17..25),
+            ("$a = { \"b\": {} }\n$a.$x.$c", "x", 17..22),
+            ("$a = { \"b\": {} }\n$a.$b[1]", "1", 23..24),
+        ] {
+            let err = eval_source_error(src);
+            assert!(matches!(&err.item, Error::RuntimeError(RuntimeError::InvalidIdentifier(n)) if n == name), "{}", src);
+            assert_eq!(err.span, span, "{}", src);
+        }
+    }
+
+    #[test]
+    fn compound_assignment() {
+        test_example_file("compound.tcsf", "6_turtles_10[3, 2]_42_[11, 2]1");
+        test_example_file_tokens("compound.tcsf", "6_turtles_10[3, 2]_42_[11, 2]1");
+        let err = eval_source_error("$a = \"x\"\n$a -= 1");
+        assert!(matches!(err.item, Error::TypeError(_)));
+        assert_eq!(err.span, 15..16);
+    }
+
+    #[test]
+    fn scopes() {
+        test_example_file("scopes.tcsf", "4_1014_703_2");
+        test_example_file_tokens("scopes.tcsf", "4_1014_703_2");
+        // Variables created inside a function stay local
+        let err = eval_source_error("fn init() {\n$created = 1\n}\ninit()\n$created");
+        assert!(matches!(&err.item, Error::RuntimeError(RuntimeError::InvalidIdentifier(n)) if n == "created"));
+    }
+
+    #[test]
+    fn recursion_limit() {
+        test_example_file("recursion.tcsf", "90");
+        let src = "fn down($n) {\nreturn down($n + 1)\n}\nfn start() {\nreturn down(0)\n}\nstart()";
+        let err = eval_source_error(src);
+        assert!(matches!(&err.item, Error::StackOverflow(trace) if trace.len() == 5 && trace.iter().all(|name| name == "down")));
+        assert_eq!(err.span, 21..33);
+
+        let ast = parser::parse(&read_example_file("recursion.tcsf")).unwrap();
+        let mut context = interpreter::Context::new_parent(None);
+        context.max_call_depth = 10;
+        let err = context.eval_root(&ast).unwrap_err();
+        assert!(matches!(&err.item, Error::StackOverflow(trace) if trace[0] == "count"));
+    }
+
+    #[test]
+    fn nested_recursion_limit() {
+        // Expressions nested in the body take the native stack too, not only the calls
+        let src = "fn rec($n) {\nif $n >= 0 {\nloop 1 {\nfor $i in [1] {\n$x = [1, {\"a\": [-rec($n + 1) + 1]}]\n}\n}\n}\n}\nrec(0)";
+        let ast = parser::parse(src).unwrap();
+        for engine in [Engine::TreeWalker, Engine::Bytecode] {
+            let ast = ast.clone();
+            // The default limit fits into the stack of a spawned thread
+            let err = std::thread::Builder::new()
+                .stack_size(2 * 1024 * 1024)
+                .spawn(move || {
+                    let mut context = interpreter::Context::new_parent(None);
+                    context.engine = engine;
+                    context.eval_root(&ast).unwrap_err().item
+                })
+                .unwrap()
+                .join()
+                .unwrap();
+            assert!(matches!(&err, Error::StackOverflow(trace) if trace.iter().all(|name| name == "rec")), "{:?}", engine);
+        }
+    }
+
+    #[test]
+    fn budget() {
+        let ast = parser::parse("$i = 0\nloop {\n$i += 1\n}").unwrap();
+        let mut context = interpreter::Context::new_parent(None);
+        context.set_budget(interpreter::Budget { steps: Some(1000), time: None });
+        let err = context.eval_root(&ast).unwrap_err();
+        assert!(matches!(err.item, Error::BudgetExhausted(1000)));
+        assert_eq!(context.steps(), 1000);
+
+        let mut context = interpreter::Context::new_parent(None);
+        context.set_budget(interpreter::Budget { steps: None, time: Some(Duration::from_millis(50)) });
+        let err = context.eval_root(&ast).unwrap_err();
+        assert!(matches!(err.item, Error::BudgetExhausted(steps) if steps > 0));
+
+        let ast = parser::parse(&read_example_file("recursion.tcsf")).unwrap();
+        let mut context = interpreter::Context::new_parent(None);
+        context.set_budget(interpreter::Budget { steps: Some(10000), time: Some(Duration::from_secs(60)) });
+        context.eval_root(&ast).unwrap();
+        assert!(context.steps() > 90);
+    }
+
+    #[test]
+    fn call_trace() {
+        let src = "fn inner($x) {\nreturn $x / 0\n}\nfn outer() {\nreturn inner(1)\n}\nouter()";
+        let ast = parser::parse(src).unwrap();
+        let mut context = interpreter::Context::new_parent(None);
+        let err = context.eval_root(&ast).unwrap_err();
+        assert!(matches!(err.item, Error::RuntimeError(RuntimeError::DivisionByZero)));
+        let trace = context.error_trace();
+        assert_eq!(trace.iter().map(|frame| frame.name.as_str()).collect::<Vec<_>>(), ["outer", "inner"]);
+        assert_eq!(trace[0].span, 62..69);
+        assert_eq!(trace[1].span, 51..59);
+        let message = err.build_message(src, trace);
+        assert!(message.ends_with("\n  outer (line 7, column 1)\n  inner (line 5, column 8)"), "{}", message);
+
+        // Errors outside of functions have no trace
+        let ast = parser::parse("fn ok() {\nreturn 1\n}\nok()\n$missing").unwrap();
+        context.eval_root(&ast).unwrap_err();
+        assert!(context.error_trace().is_empty());
+    }
+
+    #[test]
+    fn closure_scopes() {
+        // Functions stored in the scope they capture must not keep a finished call alive
+        let src = "$data = [1]\nfn outer($list) {\nfn named() 1\n$anonymous = fn() $list\nreturn named()\n}\nloop 10 {\nouter($data)\n}\n$result = 0\nfn fails($list) {\nfn inner() 1\n$list[5]\n}\nfails($data)";
+        let ast = parser::parse(src).unwrap();
+        for engine in [Engine::TreeWalker, Engine::Bytecode] {
+            let mut context = interpreter::Context::new_parent(None);
+            context.engine = engine;
+            context.eval_root(&ast).unwrap_err();
+            let Some(Value::List(data)) = context.stack[0].borrow().vars.get("data").cloned() else { panic!("Missing $data") };
+            assert_eq!(Rc::strong_count(&data), 2, "{:?}", engine);
+        }
+
+        // Scopes that escaped with a closure stay usable
+        let src = "fn counter() {\n$count = 0\n$next = fn() {\n$count += 1\nreturn $count\n}\nreturn $next\n}\n$next = counter()\n$next()\nreturn $next()";
+        let ast = parser::parse(src).unwrap();
+        eval_example("counter", ast, "2");
+    }
+
+    #[test]
+    fn bytecode_engine() {
+        let ast = parser::parse(&read_example_file("recursion.tcsf")).unwrap();
+        let mut context = interpreter::Context::new_parent(None);
+        context.engine = Engine::Bytecode;
+        context.max_call_depth = 10;
+        let err = context.eval_root(&ast).unwrap_err();
+        assert!(matches!(&err.item, Error::StackOverflow(trace) if trace[0] == "count"));
+
+        let ast = parser::parse("$i = 0\nloop {\n$i += 1\n}").unwrap();
+        let mut context = interpreter::Context::new_parent(None);
+        context.engine = Engine::Bytecode;
+        context.set_budget(interpreter::Budget { steps: Some(1000), time: None });
+        let err = context.eval_root(&ast).unwrap_err();
+        assert!(matches!(err.item, Error::BudgetExhausted(1000)));
+
+        let src = "fn inner($x) {\nreturn $x / 0\n}\nfn outer() {\nreturn inner(1)\n}\nouter()";
+        let ast = parser::parse(src).unwrap();
+        let mut context = interpreter::Context::new_parent(None);
+        context.engine = Engine::Bytecode;
+        let err = context.eval_root(&ast).unwrap_err();
+        assert_eq!(err.span, 22..28);
+        let trace = context.error_trace();
+        assert_eq!(trace.iter().map(|frame| frame.name.as_str()).collect::<Vec<_>>(), ["outer", "inner"]);
+        assert_eq!(trace[1].span, 51..59);
+
+        // Global variables stay in the context for the next run
+        let ast = parser::parse("$shared = fn($x) $x * 2").unwrap();
+        context.eval_root(&ast).unwrap();
+        let ast = parser::parse("$shared(21)").unwrap();
+        assert_eq!(context.eval_root(&ast).unwrap().to_string(), "42");
+        // Functions keep reading the globals they were compiled with in later programs
+        let source = parser::parse("$k = 10\n$scale = fn($x) $x * $k\n$a = 1\n$b = 2\n$c = 3\n$third = fn() $c").unwrap();
+        context.eval_root(&source).unwrap();
+        let later = parser::parse("$zz = 1\n$scale(2)").unwrap();
+        assert_eq!(context.eval_root(&later).unwrap().to_string(), "20");
+        let later = parser::parse("$third()").unwrap();
+        assert_eq!(context.eval_root(&later).unwrap().to_string(), "3");
+
+        // Functions of one engine cannot be called by the other one
+        context.engine = Engine::TreeWalker;
+        let err = context.eval_root(&ast).unwrap_err();
+        assert!(matches!(err.item, Error::EngineChanged));
+        assert_eq!(err.span, 0..11);
+        context.engine = Engine::Bytecode;
+        assert_eq!(context.eval_root(&ast).unwrap().to_string(), "42");
+    }
+
+    #[test]
+    fn resolver() {
+        let src = "$count = 0\nfn add($a, $b) {\nreturn $a + $b\n$count = 1\n}\nadd(1)\nstring($cuont)\nbreak";
+        let ast = parser::parse(src).unwrap();
+        let context = interpreter::Context::new_parent(None);
+        let problems = context.resolve(&ast);
+        let found = problems.iter().map(|problem| (problem.severity, format!("{:?}", problem.error))).collect::<Vec<_>>();
+        assert_eq!(found, [
+            (Severity::Warning, "UnreachableCode @ 43..54".to_owned()),
+            (Severity::Error, "RuntimeError(InvalidArgCount(1, 2)) @ 56..62".to_owned()),
+            (Severity::Warning, "RuntimeError(InvalidIdentifier(\"cuont\")) @ 70..76".to_owned()),
+            (Severity::Error, "BreakOutsideLoop @ 78..83".to_owned()),
+        ]);
+        let plain = diagnostics::render(src, &[problems[0].diagnostic()], Format::Plain, Language::English);
+        assert!(plain.starts_with("warning: This code is never run\n --> line 4, column 1"), "{}", plain);
+
+        // Names created anywhere in the visible scopes are fine, whatever the order
+        let src = "fn later() {\nglobal $level = 1\n}\nfn outer($x) {\nfn inner() $x + $level + $total\nloop 2 { continue }\ninner()\n}\n$total = 0";
+        assert!(context.resolve(&parser::parse(src).unwrap()).is_empty());
+
+        // Examples that run must pass the checks, names skipped by short-circuiting are only warnings
+        let root_dir = path::PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("../tests");
+        for entry in fs::read_dir(root_dir).unwrap() {
+            let path = entry.unwrap().path();
+            let Ok(ast) = parser::parse(&fs::read_to_string(&path).unwrap()) else { continue };
+            let errors = context
+                .resolve(&ast)
+                .into_iter()
+                .filter(|problem| problem.severity == Severity::Error)
+                .collect::<Vec<_>>();
+            assert!(errors.is_empty(), "{}: {:?}", path.display(), errors);
+        }
+        let problems = context.resolve(&parser::parse(&read_example_file("bools.tcsf")).unwrap());
+        let found = problems.iter().map(|problem| (problem.severity, format!("{:?}", problem.error))).collect::<Vec<_>>();
+        assert_eq!(found, [
+            (Severity::Warning, "RuntimeError(InvalidIdentifier(\"undefined\")) @ 245..255".to_owned()),
+            (Severity::Warning, "RuntimeError(InvalidIdentifier(\"undefined\")) @ 285..295".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn optimizer() {
+        let ast = optimizer::optimized(parser::parse("loop 4 * 4 {\n}\nif 1 < 2 {\n$a = -(2 ** 3)\n} else {\n$b = 1\n}\nif false {\n}").unwrap());
+        let Expression::Block(block) = &ast.item else { panic!("{:?}", ast) };
+        assert!(matches!(&block[0].item, Expression::LoopFinite { iters, .. } if matches!(iters.item, Expression::Int(16)) && iters.span == (5..10)));
+        let Expression::Block(body) = &block[1].item else { panic!("{:?}", block[1]) };
+        assert_eq!(block[1].span, 15..58);
+        assert!(matches!(&body[0].item, Expression::Assignment { value, .. } if matches!(value.item, Expression::Int(-8)) && value.span == (31..39)));
+        assert!(matches!(block[2].item, Expression::None));
+        assert_eq!(block[2].span, 59..71);
+
+        // Failing operations stay for the runtime and report the same span
+        let ast = optimizer::optimized(parser::parse("$x = 2 * 3 // 0").unwrap());
+        let Expression::Block(block) = &ast.item else { panic!("{:?}", ast) };
+        assert!(matches!(&block[0].item, Expression::Assignment { value, .. } if matches!(&value.item, Expression::IntDivision(a, _) if matches!(a.item, Expression::Int(6)))));
+        assert_eq!(eval_source_error("$x = 2 * 3 // 0").span, 5..15);
+        // The right operand is skipped whatever it is
+        assert!(matches!(optimizer::optimized(parser::parse("false and $missing").unwrap()).item, Expression::Block(block) if matches!(block[0].item, Expression::Bool(false))));
+    }
+
+    #[test]
+    fn messages() {
+        let runtime = [
+            ("$x = 1 // 0", "Division by zero", "Dělení nulou"),
+            ("$list = [1]\n$list[3]", "Index 3 is out of range of a list with 1 items", "Index 3 je mimo rozsah seznamu s 1 prvky"),
+            ("fn two($a, $b) {\n}\ntwo(1)", "Wrong number of arguments: got 1, expected 2", "Špatný počet argumentů: zadáno 1, očekáváno 2"),
+            ("break", "break can be used only inside a loop", "break lze použít jen uvnitř cyklu"),
+            ("\"a\" - 1", "This operator cannot be used on string and int", "Tento operátor nelze použít na typy string a int"),
+            ("-true", "This operator cannot be used on bool", "Tento operátor nelze použít na typ bool"),
+            ("$n = 1\n$n[0]", "int cannot be indexed", "Typ int nelze indexovat"),
+            ("$list = [1]\n$list[\"a\"]", "List index must be an int, not string", "Index seznamu musí být int, ne string"),
+            ("$n = 1\n$n.$field", "This is not an object", "Toto není objekt"),
+        ];
+        for (src, english, czech) in runtime {
+            let err = eval_source_error(src);
+            assert_eq!(err.item.to_string(), english);
+            assert_eq!(err.item.message(Language::English), english);
+            assert_eq!(err.item.message(Language::Czech), czech);
+        }
+        let syntax = [
+            ("(1 + 2", "Unexpected end of program", "Neočekávaný konec programu"),
+            ("\"{1\"", "Unmatched brace in a string", "Neuzavřená složená závorka v řetězci"),
+        ];
+        for (src, english, czech) in syntax {
+            let errors = parser::parse(src).unwrap_err();
+            assert_eq!(errors[0].item.message(Language::English), english, "{}", src);
+            assert_eq!(errors[0].item.message(Language::Czech), czech, "{}", src);
+        }
+        assert_eq!(Error::InvalidNumber("1.5e".to_owned()).message(Language::Czech), "1.5e není platné číslo");
+        assert_eq!(Error::InvalidSyntax("detail".to_owned()).message(Language::English), "Invalid syntax: detail");
+        let expected = [
+            (vec![Some(Token::RightParent)], "Expected ), found end of line"),
+            (vec![Some(Token::Comma), None, Some(Token::RightSquare)], "Expected ,, ] or end of program, found end of line"),
+        ];
+        for (tokens, english) in expected {
+            let err = Error::UnexpectedToken {
+                found: Some(Token::Newline),
+                expected: tokens,
+                context: None,
+            };
+            assert_eq!(err.message(Language::English), english);
+        }
+        assert_eq!(Language::from_code(Language::Czech.code()), Some(Language::Czech));
+    }
+
+    #[test]
+    fn syntax_recovery() {
+        let src = "loop 3 {\n  print 1 )\n  print 2\n}\n}\nprint ]";
+        let errors = parser::parse(src).unwrap_err();
+        let spans = errors.iter().map(|err| err.span.clone()).collect::<Vec<_>>();
+        assert_eq!(spans, [19..20, 33..34, 41..42]);
+        let contexts = [
+            ("if $a == { 1 }\nprint 1", "Unexpected } (in the condition of if)"),
+            ("for $i 1 ) { }", "Unexpected ) (in the header of the for loop)"),
+            ("fn ab($x 1) { }", "Expected ) or ,, found 1 (in the arguments of the function)"),
+        ];
+        for (src, message) in contexts {
+            let errors = parser::parse(src).unwrap_err();
+            assert_eq!(errors.len(), 1, "{}", src);
+            assert_eq!(errors[0].item.message(Language::English), message, "{}", src);
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let src = "$jméno = \"čau\"\n$x = $jméno / 2\n";
+        let errors = [
+            Spanned::new(Error::RuntimeError(RuntimeError::DivisionByZero), 22..33),
+            Spanned::new(Error::TypeError(TypeErrorKind::NotAnObject), 10..22),
+        ];
+        let diagnostics = errors.iter().map(Diagnostic::from).collect::<Vec<Diagnostic>>();
+        let expected = [
+            "error: Division by zero",
+            " --> line 2, column 6",
+            "2 | $x = $jméno / 2",
+            "  |      ^^^^^^^^^^",
+            "",
+            "error: This is not an object",
+            " --> line 1, column 10",
+            "1 | $jméno = \"čau\"",
+            "  |          ^^^^^",
+            "2 | $x = $jméno / 2",
+            "  | ^^^^^",
+        ];
+        assert_eq!(diagnostics::render(src, &diagnostics, Format::Plain, Language::English), expected.join("\n"));
+
+        let ansi = diagnostics::render(src, &diagnostics[..1], Format::Ansi, Language::Czech);
+        assert!(ansi.contains("chyba\x1b[0m: \x1b[33mDělení nulou"), "{}", ansi);
+        assert!(ansi.contains("\x1b[31m^^^^^^^^^^\x1b[0m"), "{}", ansi);
+
+        let json = diagnostics::render(src, &diagnostics, Format::Json, Language::English);
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json[0]["message"], "Division by zero");
+        assert_eq!(json[0]["start"], serde_json::json!({"line": 2, "column": 6}));
+        assert_eq!(json[0]["end"], serde_json::json!({"line": 2, "column": 16}));
+        assert_eq!(json[1]["span"], serde_json::json!({"start": 10, "end": 22}));
+
+        // Errors at the end of the program point after the last character
+        let errors = parser::parse("(1 +").unwrap_err();
+        let plain = diagnostics::render("(1 +", &[Diagnostic::from(&errors[0])], Format::Plain, Language::English);
+        assert!(plain.ends_with(" --> line 1, column 5\n1 | (1 +\n  |     ^"), "{}", plain);
+    }
+
+    #[test]
+    fn lambdas() {
+        test_example_file(
+            "lambdas.tcsf",
+            "8_2_10_hi turtle_42_2_<Anonymous function ($x)>_<Anonymous function ()>",
+        );
+    }
+
+    #[test]
+    fn lists() {
+        test_example_file("lists.tcsf", "5_6_2_10_5_8_[\"first\", 2, \"three\", 4, \"last\"]");
+    }
+
+    #[test]
+    fn list_index_errors() {
+        for index in ["-1", "3"] {
+            let err = eval_source_error(&format!("$list = [1, 2, 3]\nreturn $list[{}]", index));
+            assert!(matches!(err.item, Error::RuntimeError(RuntimeError::IndexOutOfRange(_, 3))));
+        }
+        // The value removes the item that the target refers to
+        let err = eval_source_error("$l = [1, 2]\n$l[1] += pop($l)");
+        assert!(matches!(err.item, Error::RuntimeError(RuntimeError::IndexOutOfRange(1, 1))));
+        assert_eq!(err.span, 12..28);
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        for (src, span) in [("5 / 0", 0..5), ("1 + 5 % 0", 4..9), ("7 // 0", 0..6), ("0 ** -1", 0..7)] {
+            let err = eval_source_error(src);
+            assert!(matches!(err.item, Error::RuntimeError(RuntimeError::DivisionByZero)));
+            assert_eq!(err.span, span);
+        }
+        for src in ["2147483647 + 1", "-2147483647 - 2", "65536 * 65536", "2 ** 31", "-(-2147483647 - 1)"] {
+            let err = eval_source_error(src);
+            assert!(matches!(err.item, Error::RuntimeError(RuntimeError::IntegerOverflow)), "{}", src);
+        }
+    }
+
     #[test]
     fn padding() {
         test_example_file("padding.tcsf", "111");
     }
 
 
+    #[test]
+    fn bools() {
+        test_example_file("bools.tcsf", "abcdefgh_true_false");
+    }
+
+    #[test]
+    fn if_else() {
+        test_example_file("else.tcsf", "+-0_abcd_y_y");
+        test_example_file_tokens("else.tcsf", "+-0_abcd_y_y");
+    }
+
+    #[test]
+    fn loop_continue() {
+        test_example_file("continue.tcsf", "024_1245_34_345_8_-1");
+    }
+
+    #[test]
+    fn loop_control_outside_loop() {
+        let err = eval_source_error("fn skip() {\n    break\n}\nskip()");
+        assert!(matches!(err.item, Error::BreakOutsideLoop));
+        let err = eval_source_error("fn skip() {\n    continue\n}\nskip()");
+        assert!(matches!(err.item, Error::ContinueOutsideLoop));
+        let err = eval_source_error("continue");
+        assert!(matches!(err.item, Error::ContinueOutsideLoop));
+    }
+
+    #[test]
+    fn loop_for_each() {
+        test_example_file("foreach.tcsf", "14_go-b-turtle-a-_urle_6_12");
+        let err = eval_source_error("for $x in 5 {\n}");
+        assert!(matches!(err.item, Error::InvalidForCollection));
+        assert_eq!(err.span, 10..11);
+    }
+
+    #[test]
+    fn escapes() {
+        let output = "a\tb\nc_say \"hi\"_back\\slash_\u{1F422} 1_Hi_Image: a\"b";
+        test_example_file("escapes.tcsf", output);
+        test_example_file_tokens("escapes.tcsf", output);
+        for (src, span, sequence) in [
+            ("\"ok \\q\"", 4..6, "\\q"),
+            ("$x = \"\\u{110000}\"", 6..16, "\\u{110000}"),
+            ("k\"\\u{12\"", 2..7, "\\u{12"),
+        ] {
+            let errors = parser::parse(src).unwrap_err();
+            assert!(matches!(&errors[0].item, Error::InvalidEscape(s) if s == sequence), "{}", src);
+            assert_eq!(errors[0].span, span, "{}", src);
+        }
+        for text in ["plain", "a\nb\t\"c\"", "back\\slash \\n", "\u{7}bell \u{1F422}"] {
+            assert_eq!(tokens::unescape_string(&tokens::escape_string(text)).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn interpolation() {
+        let output = "x = 5, pos = [1, 2]_610_{literal} {$x}_name: turtle, len 6_nested inner 5_quoted }{_tab\tbig";
+        test_example_file("interpolation.tcsf", output);
+        test_example_file_tokens("interpolation.tcsf", output);
+        // Quotes and braces in string literals of the expression
+        eval_example("escaped quote", parser::parse("\"{\\\"\\\\\\\"}}\\\"}\"").unwrap(), "\"}");
+        for (src, span) in [("\"{1 / 0}\"", 2..7), ("\"\\n\\\"{1 / 0}\"", 6..11)] {
+            let err = eval_source_error(src);
+            assert!(matches!(err.item, Error::RuntimeError(RuntimeError::DivisionByZero)), "{}", src);
+            assert_eq!(err.span, span, "{}", src);
+        }
+        for (src, span) in [("$s = \"a } b\"", 8..9), ("$s = \"a {$x\"", 8..11), ("\"\\t{$x +}\"", 3..9)] {
+            let errors = parser::parse(src).unwrap_err();
+            assert!(matches!(errors[0].item, Error::UnmatchedBrace | Error::UnexpectedToken { .. }), "{}", src);
+            assert!(errors[0].span.start >= span.start && errors[0].span.end <= span.end, "{} {:?}", src, errors[0].span);
+        }
+    }
+
     #[test]
     fn loop_while() {
         test_example_file("while.tcsf", "5_4_3_2_1_0_");
     }
 
     fn test_example_file(name: &str, output: &str) {
+        let src = read_example_file(name);
+        let ast = parser::parse(&src).expect(&format!("Cannot parse example file \"{}\"", name));
+        eval_example(name, ast, output);
+    }
+
+    /// Parses the example the way the editor does, from a flat list of icon tokens
+    fn test_example_file_tokens(name: &str, output: &str) {
+        let src = read_example_file(name);
+        let tokens = parser::get_tokens(&src)
+            .into_iter()
+            .collect::<Result<Vec<Token>, _>>()
+            .unwrap_or_else(|_| panic!("Cannot tokenize example file \"{}\"", name));
+        let ast = parser::parse_tokens(tokens)
+            .unwrap_or_else(|_| panic!("Cannot parse example file \"{}\"", name));
+        eval_example(name, ast, output);
+    }
+
+    /// Both engines have to report the same error, with or without the optimizer
+    fn eval_source_error(src: &str) -> Spanned<Error> {
+        let ast = parser::parse(src).unwrap();
+        let mut context = interpreter::Context::new_parent(None);
+        let err = context.eval_root(&ast).unwrap_err();
+        let mut context = interpreter::Context::new_parent(None);
+        context.engine = Engine::Bytecode;
+        let compiled_err = context.eval_root(&ast).unwrap_err();
+        assert_eq!(format!("{:?}", compiled_err), format!("{:?}", err), "Engines differ for {:?}", src);
+        let mut context = interpreter::Context::new_parent(None);
+        context.optimize = false;
+        let unoptimized_err = context.eval_root(&ast).unwrap_err();
+        assert_eq!(format!("{:?}", unoptimized_err), format!("{:?}", err), "Optimizer changes the error of {:?}", src);
+        err
+    }
+
+    fn read_example_file(name: &str) -> String {
         let root_dir = &env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
         let mut path_buf = path::PathBuf::new();
         path_buf.push(root_dir);
@@ -44,13 +548,20 @@ mod test_examples {
         path_buf.push("tests");
         path_buf.push(name);
         println!("{}", path_buf.display());
-        let src = fs::read_to_string(path_buf).expect(&format!("Cannot read example file \"{}\"", name));
-        let ast = parser::parse(&src).expect(&format!("Cannot parse example file \"{}\"", name));
+        fs::read_to_string(path_buf).expect(&format!("Cannot read example file \"{}\"", name))
+    }
 
-        let mut context = interpreter::Context::new_parent(None);
-        let _result = context.eval_root(&ast).expect(&format!("Evaluation of example file failed \"{}\"", name)).to_string();
-        if output != _result {
-            panic!("Invalid result. Expected: \"{}\". Got: \"{}\".", output, _result);
+    fn eval_example(name: &str, ast: Spanned<Expression>, output: &str) {
+        for engine in [Engine::TreeWalker, Engine::Bytecode] {
+            for optimize in [false, true] {
+                let mut context = interpreter::Context::new_parent(None);
+                context.engine = engine;
+                context.optimize = optimize;
+                let _result = context.eval_root(&ast).unwrap_or_else(|_| panic!("Evaluation of example file failed \"{}\" ({:?}, optimize: {})", name, engine, optimize)).to_string();
+                if output != _result {
+                    panic!("Invalid result ({:?}, optimize: {}). Expected: \"{}\". Got: \"{}\".", engine, optimize, output, _result);
+                }
+            }
         }
     }
 }
\ No newline at end of file
@@ -1,9 +1,20 @@
-use logos::{Lexer, Logos, Source};
+use std::ops::Range;
+
+use logos::{Lexer, Logos};
 use serde::{Serialize, Deserialize};
 
+#[derive(Default, Debug, PartialEq, Clone)]
+pub enum LexingError {
+    #[default]
+    InvalidToken,
+    /// Malformed escape sequence, the range is relative to the start of the token
+    InvalidEscape(Range<usize>),
+}
+
 #[derive(Logos, Debug, PartialEq, Clone, Hash, Eq)]
 #[derive(Serialize, Deserialize)]
 #[logos(skip r"[ \t\f]+")]
+#[logos(error = LexingError)]
 pub enum Token {
     #[regex(r"#.*")]
     Comment,
@@ -23,30 +34,42 @@ pub enum Token {
     Return,
     #[token("break")]
     Break,
+    #[token("continue")]
+    Continue,
     #[token("loop")]
     Loop,
     #[token("for")]
     For,
+    #[token("in")]
+    In,
     #[token("while")]
     While,
+    #[token("local")]
+    Local,
+    #[token("global")]
+    Global,
     #[token("fn")]
     FnDef,
+    #[token("true")]
+    True,
+    #[token("false")]
+    False,
 
     #[regex(r"\$[\p{XID_Continue}]+", get_indentifier_var)]
     Variable(String),
-    #[regex(r#"@"([^"\\]|\\t|\\u|\\n|\\")*""#, get_image)]
+    #[regex(r#"@"([^"\\]|\\.)*""#, get_image)]
     Image(String),
-    #[regex(r#"k"([^"\\]|\\t|\\u|\\n|\\")*""#, get_key)]
+    #[regex(r#"k"([^"\\]|\\.)*""#, get_key)]
     Key(String),
-    #[regex(r#"f"([^"\\]|\\t|\\u|\\n|\\")*""#, get_file)]
+    #[regex(r#"f"([^"\\]|\\.)*""#, get_file)]
     File(String),
     #[regex(r#"s"([^"\\]|\\t|\\u|\\n|\\")*""#, get_tilemap)]
     Tilemap(String),
     #[regex(r"\p{XID_Start}[\p{XID_Continue}]+", get_indentifier, priority = 2)]
     Function(String),
     // Literals
-    /// String
-    #[regex(r#""([^"\\]|\\t|\\u|\\n|\\")*""#, get_string)]
+    /// String, `{expression}` inserts the value of the expression, `{{` and `}}` are literal braces
+    #[regex(r#""([^"\\]|\\.)*""#, get_string)]
     String(String),
     /// Numbers
     #[regex(r"[0-9]+", get_value, priority=4)]
@@ -83,6 +106,12 @@ pub enum Token {
     Star,
     #[token("/")]
     Slash,
+    #[token("%")]
+    Percent,
+    #[token("//")]
+    DoubleSlash,
+    #[token("**")]
+    DoubleStar,
     #[token("==")]
     Eq,
     #[token("!=")]
@@ -97,6 +126,20 @@ pub enum Token {
     Gte,
     #[token("=")]
     Assignment,
+    #[token("+=")]
+    PlusAssignment,
+    #[token("-=")]
+    MinusAssignment,
+    #[token("*=")]
+    StarAssignment,
+    #[token("/=")]
+    SlashAssignment,
+    #[token("and")]
+    And,
+    #[token("or")]
+    Or,
+    #[token("not")]
+    Not,
 }
 
 fn get_indentifier(lexer: &mut Lexer<Token>) -> String {
@@ -107,16 +150,16 @@ fn get_indentifier_var(lexer: &mut Lexer<Token>) -> String {
     lexer.slice().trim_start_matches('$').to_owned()
 }
 
-fn get_image(lexer: &mut Lexer<Token>) -> String {
-    lexer.slice().trim_start_matches("@\"").trim_end_matches('"').to_owned()
+fn get_image(lexer: &mut Lexer<Token>) -> Result<String, LexingError> {
+    get_literal(lexer, "@\"".len())
 }
 
-fn get_key(lexer: &mut Lexer<Token>) -> String {
-    lexer.slice().trim_start_matches("k\"").trim_end_matches('"').to_owned()
+fn get_key(lexer: &mut Lexer<Token>) -> Result<String, LexingError> {
+    get_literal(lexer, "k\"".len())
 }
 
-fn get_file(lexer: &mut Lexer<Token>) -> String {
-    lexer.slice().trim_start_matches("f\"").trim_end_matches('"').to_owned()
+fn get_file(lexer: &mut Lexer<Token>) -> Result<String, LexingError> {
+    get_literal(lexer, "f\"".len())
 }
 
 fn get_tilemap(lexer: &mut Lexer<Token>) -> String {
@@ -127,7 +170,90 @@ fn get_value<T: std::str::FromStr>(lexer: &mut Lexer<Token>) -> Option<T> {
     lexer.slice().parse().ok()
 }
 
-fn get_string(lexer: &mut Lexer<Token>) -> Option<String> {
+fn get_string(lexer: &mut Lexer<Token>) -> Result<String, LexingError> {
+    get_literal(lexer, "\"".len())
+}
+
+/// Decodes the content of a quoted literal that starts with a prefix of `prefix_len` bytes
+fn get_literal(lexer: &mut Lexer<Token>, prefix_len: usize) -> Result<String, LexingError> {
     let slice = lexer.slice();
-    slice.slice(1..slice.len() - 1).unwrap().parse().ok()
+    unescape_string(&slice[prefix_len..slice.len() - 1]).map_err(|range| {
+        LexingError::InvalidEscape(range.start + prefix_len..range.end + prefix_len)
+    })
+}
+
+/// Decodes escape sequences (`\n`, `\t`, `\r`, `\\`, `\"` and `\u{1F422}`) in the content of a literal.
+/// Returns the byte range of the first malformed escape sequence on error.
+pub fn unescape_string(src: &str) -> Result<String, Range<usize>> {
+    unescape_string_offsets(src).map(|(result, _offsets)| result)
+}
+
+/// Same as `unescape_string`, also returns the position in `src` of every byte of the result
+/// followed by the length of `src`.
+pub fn unescape_string_offsets(src: &str) -> Result<(String, Vec<usize>), Range<usize>> {
+    let mut result = String::with_capacity(src.len());
+    let mut offsets = Vec::with_capacity(src.len() + 1);
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            offsets.extend(std::iter::repeat_n(start, c.len_utf8()));
+            continue;
+        }
+        let decoded = match chars.next() {
+            Some((_, 'n')) => Some('\n'),
+            Some((_, 't')) => Some('\t'),
+            Some((_, 'r')) => Some('\r'),
+            Some((_, '\\')) => Some('\\'),
+            Some((_, '"')) => Some('"'),
+            Some((_, 'u')) => {
+                let mut hex = String::new();
+                let mut closed = false;
+                if chars.next_if(|(_, c)| *c == '{').is_some() {
+                    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_hexdigit() || *c == '}') {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        hex.push(c);
+                    }
+                }
+                if closed && hex.len() <= 6 {
+                    u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                result.push(decoded);
+                offsets.extend(std::iter::repeat_n(start, decoded.len_utf8()));
+            }
+            None => {
+                let end = chars.peek().map_or(src.len(), |(i, _)| *i);
+                return Err(start..end);
+            }
+        }
+    }
+    offsets.push(src.len());
+    Ok((result, offsets))
+}
+
+/// Encodes a string so that `unescape_string` gives back the original
+pub fn escape_string(src: &str) -> String {
+    let mut result = String::with_capacity(src.len());
+    for c in src.chars() {
+        match c {
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            c if c.is_control() => result.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 extern crate proc_macro;
 
-use std::{collections::{HashMap, HashSet}, fmt::Display, any::Any, rc::Weak};
+use std::{collections::HashSet, fmt::Display, any::Any, rc::Weak};
 
 use crate::{error::RuntimeError, ast::{Expression, Spanned}};
 
@@ -8,6 +8,10 @@ pub type NativeFuncReturn = Result<Value, RuntimeError>;
 pub type FuncThisObject = Option<Weak<std::cell::RefCell<TSObject>>>;
 pub type NativeFuncArgs = Vec<Value>;
 pub type NativeFuncCtxArg = Box<dyn LibraryContext>;
+pub type ListRef = std::rc::Rc<std::cell::RefCell<Vec<Value>>>;
+pub type ObjectRef = std::rc::Rc<std::cell::RefCell<TSObject>>;
+/// Fields of an object in the order of insertion
+pub type ObjectFields = indexmap::IndexMap<HashableValue, Value>;
 
 #[derive(Clone)]
 pub struct NativeFunc {
@@ -18,19 +22,24 @@ pub struct NativeFunc {
 
 #[derive(Clone)]
 pub struct TSFunc {
+    pub name: Option<String>,
     pub body: Spanned<Expression>,
-    pub args: Vec<String>
+    pub args: Vec<String>,
+    /// Scopes visible at the place of definition
+    pub closure: Vec<crate::interpreter::ScopeRef>
 }
 
 #[derive(Clone)]
 pub enum Callable {
     Function(Box<TSFunc>),
-    NativeFunc(NativeFunc)
+    NativeFunc(NativeFunc),
+    /// Function compiled to bytecode, see [`crate::vm`]
+    Compiled(std::rc::Rc<crate::vm::Closure>)
 }
 
 #[derive(Debug, Clone)]
 pub struct TSObject {
-    pub fields: HashMap<HashableValue, Value>,
+    pub fields: ObjectFields,
     pub fields_props: HashSet<HashableValue>,
 }
 
@@ -50,9 +59,11 @@ pub enum Value {
     Key(String),
     Bool(bool),
     Callable(Callable),
-    Object(std::rc::Rc<std::cell::RefCell<TSObject>>),
+    Object(ObjectRef),
+    List(ListRef),
     EvaluatedReturn(Box<Value>),
     Break,
+    Continue,
     None,
 }
 
@@ -84,7 +95,7 @@ macro_rules! funcmap {
 macro_rules! funcmap_obj {
     ( $name:expr, $obj:expr, $( $x:expr ),* ) => {
         {
-            let mut map = std::collections::HashMap::new();
+            let mut map = $crate::value::ObjectFields::new();
             $(
                 map.insert(
                     $crate::value::HashableValue::String(stringify!($x).replace("::", ".").to_owned()),
@@ -115,9 +126,15 @@ pub fn unwrap_context<T: 'static>(ctx: &mut NativeFuncCtxArg) -> &mut T {
 
 impl Display for Callable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Callable::Function(_) => write!(f, "<Function>"),
-            Callable::NativeFunc(_) => write!(f, "<Native function>")
+        let (name, args) = match self {
+            Callable::Function(func) => (func.name.as_deref(), func.args.as_slice()),
+            Callable::Compiled(closure) => (closure.name(), closure.params()),
+            Callable::NativeFunc(_) => return write!(f, "<Native function>")
+        };
+        let args = args.iter().map(|arg| format!("${}", arg)).collect::<Vec<String>>();
+        match name {
+            Some(name) => write!(f, "<Function {}({})>", name, args.join(", ")),
+            None => write!(f, "<Anonymous function ({})>", args.join(", "))
         }
     }
 }
@@ -174,12 +191,27 @@ impl From<usize> for Value {
     }
 }
 
-impl From<HashMap<HashableValue, Value>> for Value {
-    fn from(value: HashMap<HashableValue, Value>) -> Self {
+impl From<ObjectFields> for Value {
+    fn from(value: ObjectFields) -> Self {
         Value::Object(std::rc::Rc::new(std::cell::RefCell::new(TSObject { fields: value, fields_props: HashSet::new() })))
     }
 }
 
+impl From<HashableValue> for Value {
+    fn from(value: HashableValue) -> Self {
+        match value {
+            HashableValue::String(str) => Value::String(str),
+            HashableValue::Int(int) => Value::Int(int),
+        }
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::List(std::rc::Rc::new(std::cell::RefCell::new(value)))
+    }
+}
+
 impl TryInto<f64> for &Value {
     fn try_into(self) -> Result<f64, crate::error::RuntimeError> {
         match self {
@@ -224,15 +256,69 @@ impl Display for Value {
             Value::Key(val) => write!(f, "Key: {}", val),
             Value::Bool(val) => write!(f, "{}", val),
             Value::Callable(val) => write!(f, "{}", val),
-            Value::Object(val) => write!(f, "{:?}", val),
+            Value::Object(_) | Value::List(_) => self.fmt_collection(f, &mut vec![]),
             Value::EvaluatedReturn(val) => write!(f, "<Return value: {}>", *val),
             Value::Break => write!(f, "<Break>"),
+            Value::Continue => write!(f, "<Continue>"),
             Value::None => write!(f, "None")
         }
     }
 }
 
 impl Value {
+    /// Formats a value inside of a list or an object, strings are quoted
+    fn fmt_item(&self, f: &mut std::fmt::Formatter<'_>, visited: &mut Vec<*const ()>) -> std::fmt::Result {
+        match self {
+            Value::String(val) => write!(f, "{:?}", val),
+            Value::Object(_) | Value::List(_) => self.fmt_collection(f, visited),
+            val => write!(f, "{}", val),
+        }
+    }
+
+    /// Formats a list or an object.
+    /// `visited` holds the collections that are being printed, so cycles are shown as `[...]` or `{...}`.
+    fn fmt_collection(&self, f: &mut std::fmt::Formatter<'_>, visited: &mut Vec<*const ()>) -> std::fmt::Result {
+        match self {
+            Value::List(list) => {
+                let ptr = std::rc::Rc::as_ptr(list) as *const ();
+                if visited.contains(&ptr) {
+                    return write!(f, "[...]");
+                }
+                visited.push(ptr);
+                write!(f, "[")?;
+                for (i, item) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_item(f, visited)?;
+                }
+                visited.pop();
+                write!(f, "]")
+            },
+            Value::Object(object) => {
+                let ptr = std::rc::Rc::as_ptr(object) as *const ();
+                if visited.contains(&ptr) {
+                    return write!(f, "{{...}}");
+                }
+                visited.push(ptr);
+                write!(f, "{{")?;
+                for (i, (key, value)) in object.borrow().fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match key {
+                        HashableValue::String(key) => write!(f, "{:?}: ", key)?,
+                        HashableValue::Int(key) => write!(f, "{}: ", key)?,
+                    }
+                    value.fmt_item(f, visited)?;
+                }
+                visited.pop();
+                write!(f, "}}")
+            },
+            val => write!(f, "{}", val),
+        }
+    }
+
     pub fn type_to_string(&self) -> &str {
         match self {
             Value::Int(_) => "int",
@@ -244,8 +330,10 @@ impl Value {
             Value::Bool(_) => "bool",
             Value::Callable(_) => "callable",
             Value::Object(_) => "object",
+            Value::List(_) => "list",
             Value::EvaluatedReturn(_) => "return",
             Value::Break => "break",
+            Value::Continue => "continue",
             Value::None => "none"
         }
     }
@@ -253,6 +341,14 @@ impl Value {
 
 impl TSObject {
     pub fn new() -> Self {
-        Self { fields: HashMap::new(), fields_props: HashSet::new() }
+        Self { fields: ObjectFields::new(), fields_props: HashSet::new() }
     }
-}
\ No newline at end of file
+}
+
+/// Converts a script index to a position in a list of length `len`
+pub fn list_index(index: i32, len: usize) -> Result<usize, RuntimeError> {
+    match usize::try_from(index) {
+        Ok(i) if i < len => Ok(i),
+        _ => Err(RuntimeError::IndexOutOfRange(index, len)),
+    }
+}
@@ -0,0 +1,761 @@
+//! Stack machine that runs programs compiled by [`crate::bytecode`].
+//!
+//! It uses the libraries, limits and global variables of a [`Context`],
+//! so both engines produce the same values and errors.
+
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+use crate::{
+    ast::Spanned,
+    bytecode::{Failure, Function, Instruction, IntCheck, Program},
+    cycles,
+    error::{CallFrame, Error, RuntimeError, TypeErrorKind},
+    interpreter::{apply_math_operator, Context, Place, STACK_OVERFLOW_TRACE_LEN},
+    value::{list_index, Callable, HashableValue, ObjectFields, ObjectRef, TSObject, Value},
+};
+
+/// Compiled function together with the scopes it was defined in
+pub struct Closure {
+    function: Rc<Function>,
+    scope: Option<Rc<Scope>>,
+}
+
+impl Closure {
+    pub fn name(&self) -> Option<&str> {
+        self.function.name.as_deref()
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.function.slots[..self.function.params]
+    }
+}
+
+/// Variables of one function call, shared with closures defined in it.
+/// Unset slots belong to variables that were not created yet.
+struct Scope {
+    slots: RefCell<Vec<Option<Value>>>,
+    /// Scope of the enclosing function, none at the top level
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    fn ancestor(self: &Rc<Self>, depth: usize) -> &Rc<Scope> {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = scope.parent.as_ref().expect("Missing enclosing scope");
+        }
+        scope
+    }
+}
+
+struct Frame {
+    function: Rc<Function>,
+    ip: usize,
+    /// None at the top level, whose variables are global
+    scope: Option<Rc<Scope>>,
+    /// Stack length at the start of the call
+    base: usize,
+    places: usize,
+    loops: Vec<Loop>,
+    /// Whether it is a function call and not the top level
+    is_call: bool,
+}
+
+struct Loop {
+    /// Stack length before the state of the loop
+    base: usize,
+    /// Stack length with the state of the loop
+    body: usize,
+    places: usize,
+    next: usize,
+    end: usize,
+}
+
+struct Vm<'a> {
+    ctx: &'a mut Context,
+    globals: Vec<Option<Value>>,
+    /// Global variables that are evaluated when read
+    global_props: Vec<bool>,
+    stack: Vec<Value>,
+    places: Vec<Place>,
+    frames: Vec<Frame>,
+    /// Function calls for traces, the innermost is the last one
+    calls: Vec<CallFrame>,
+}
+
+/// Runs the program with the global variables of the context and stores them back
+pub(crate) fn run(ctx: &mut Context, program: &Program) -> Result<Value, Spanned<Error>> {
+    let (globals, global_props) = {
+        let global = ctx.stack[0].borrow();
+        ctx.globals
+            .names
+            .iter()
+            .map(|name| {
+                (
+                    global.vars.get(name).cloned(),
+                    global.vars_props.contains(name),
+                )
+            })
+            .unzip()
+    };
+    let mut vm = Vm {
+        ctx,
+        globals,
+        global_props,
+        stack: vec![],
+        places: vec![],
+        frames: vec![],
+        calls: vec![],
+    };
+    vm.frames.push(Frame {
+        function: program.main.clone(),
+        ip: 0,
+        scope: None,
+        base: 0,
+        places: 0,
+        loops: vec![],
+        is_call: false,
+    });
+    let result = vm.execute(0);
+    // The innermost function that failed sees the whole trace
+    if result.is_err() && !vm.calls.is_empty() {
+        vm.ctx.error_trace = Some(vm.calls.clone());
+    }
+    // Frames of failed calls are left on the stack
+    vm.stack.clear();
+    vm.places.clear();
+    while let Some(frame) = vm.frames.pop() {
+        if let Some(scope) = frame.scope {
+            release_scope(scope);
+        }
+    }
+    let mut global = vm.ctx.stack[0].borrow_mut();
+    for (name, value) in vm.ctx.globals.names.iter().zip(vm.globals) {
+        if let Some(value) = value {
+            global.vars.insert(name.to_owned(), value);
+        }
+    }
+    result
+}
+
+impl Vm<'_> {
+    /// Runs until the call stack returns to the given depth
+    fn execute(&mut self, depth: usize) -> Result<Value, Spanned<Error>> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let instruction = frame.function.code[frame.ip];
+            frame.ip += 1;
+            self.ctx.check_limits().map_err(|err| self.error(err))?;
+            match instruction {
+                Instruction::Constant(index) => {
+                    let value = self.function().constants[index].clone();
+                    self.stack.push(value);
+                }
+                Instruction::Pop => {
+                    self.pop();
+                }
+                Instruction::LoadGlobal(global) => {
+                    let value = match &self.globals[global] {
+                        Some(value) => value.clone(),
+                        None => {
+                            let name = self.ctx.globals.names[global].to_owned();
+                            return Err(self.error(invalid_identifier(name)));
+                        }
+                    };
+                    let value = match self.global_props[global] {
+                        true => self.property(value)?,
+                        false => value,
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::StoreGlobal(global) => {
+                    let value = self.pop();
+                    let oldval = self.globals[global].replace(value);
+                    self.stack.push(oldval.unwrap_or(Value::None));
+                }
+                Instruction::LoadVariable(index) => {
+                    let value = self.load_variable(index)?;
+                    self.stack.push(value);
+                }
+                Instruction::StoreVariable(index) => {
+                    let value = self.pop();
+                    let oldval = self.store_variable(index, value);
+                    self.stack.push(oldval);
+                }
+                Instruction::StoreSlot(slot) => {
+                    let value = self.pop();
+                    let oldval = self.scope().slots.borrow_mut()[slot].replace(value);
+                    self.stack.push(oldval.unwrap_or(Value::None));
+                }
+                Instruction::LoadMember(name) => {
+                    let key = self.key(name);
+                    let value = match self.pop() {
+                        Value::Object(object) => self.get_field(&object, &key)?,
+                        _ => return Err(self.error(not_an_object())),
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::CheckIndexable => match self.peek(0) {
+                    Value::List(_) | Value::Object(_) => {}
+                    value => {
+                        let err = Error::TypeError(TypeErrorKind::NotIndexable(
+                            value.type_to_string().to_owned(),
+                        ));
+                        return Err(self.error(err));
+                    }
+                },
+                Instruction::PlaceMember(name) => {
+                    let key = self.key(name);
+                    match self.pop() {
+                        Value::Object(object) => self.places.push(Place::Object(object, key)),
+                        _ => return Err(self.error(not_an_object())),
+                    }
+                }
+                Instruction::PlaceIndex => {
+                    let index = self.pop();
+                    let place = match self.pop() {
+                        Value::Object(object) => {
+                            let key = HashableValue::try_from(index)
+                                .map_err(|err| self.error(Error::RuntimeError(err)))?;
+                            Place::Object(object, key)
+                        }
+                        list => {
+                            let Value::List(list) = list else {
+                                unreachable!("Checked by CheckIndexable")
+                            };
+                            let i = match index {
+                                Value::Int(i) => list_index(i, list.borrow().len())
+                                    .map_err(|err| self.error(Error::RuntimeError(err)))?,
+                                value => {
+                                    let err = Error::TypeError(TypeErrorKind::ListIndex(
+                                        value.type_to_string().to_owned(),
+                                    ));
+                                    return Err(self.error(err));
+                                }
+                            };
+                            Place::List(list, i)
+                        }
+                    };
+                    self.places.push(place);
+                }
+                Instruction::DupPlace => {
+                    let place = self.places.last().unwrap().clone();
+                    self.places.push(place);
+                }
+                Instruction::LoadPlace => {
+                    let value = match self.places.pop().unwrap() {
+                        Place::List(list, i) => {
+                            let list = list.borrow();
+                            let i = list_index(i as i32, list.len())
+                                .map_err(|err| self.error(Error::RuntimeError(err)))?;
+                            list[i].clone()
+                        }
+                        Place::Object(object, key) => self.get_field(&object, &key)?,
+                        Place::Variable(_) => unreachable!("Variables do not use places"),
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::StorePlace => {
+                    let value = self.pop();
+                    let oldval = match self.places.pop().unwrap() {
+                        // Evaluating the value may have shortened the list
+                        Place::List(list, i) => {
+                            let mut list = list.borrow_mut();
+                            let i = list_index(i as i32, list.len())
+                                .map_err(|err| self.error(Error::RuntimeError(err)))?;
+                            Some(std::mem::replace(&mut list[i], value))
+                        }
+                        Place::Object(object, key) => object.borrow_mut().fields.insert(key, value),
+                        Place::Variable(_) => unreachable!("Variables do not use places"),
+                    };
+                    self.stack.push(oldval.unwrap_or(Value::None));
+                }
+                Instruction::CheckInt(depth, check) => {
+                    if !matches!(self.peek(depth), Value::Int(_)) {
+                        let err = match check {
+                            IntCheck::IterationCount => Error::InvalidIterationCount,
+                            IntCheck::ForStart => Error::InvalidForStart,
+                            IntCheck::ForEnd => Error::InvalidForEnd,
+                            IntCheck::ForStep => Error::InvalidForStep,
+                        };
+                        return Err(self.error(err));
+                    }
+                }
+                Instruction::CheckKey => {
+                    if let Err(err) = HashableValue::try_from(self.peek(0).clone()) {
+                        return Err(self.error(Error::RuntimeError(err)));
+                    }
+                }
+                Instruction::MakeList(len) => {
+                    let items = self.stack.split_off(self.stack.len() - len);
+                    self.stack.push(items.into());
+                }
+                Instruction::MakeObject(len) => {
+                    let mut fields = ObjectFields::new();
+                    let mut items = self.stack.split_off(self.stack.len() - len * 2).into_iter();
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        let key = HashableValue::try_from(key)
+                            .map_err(|err| self.error(Error::RuntimeError(err)))?;
+                        fields.insert(key, value);
+                    }
+                    self.stack
+                        .push(Value::Object(Rc::new(RefCell::new(TSObject {
+                            fields,
+                            fields_props: Default::default(),
+                        }))));
+                }
+                Instruction::Interpolate(len) => {
+                    let parts = self.stack.split_off(self.stack.len() - len);
+                    let result: String = parts.iter().map(Value::to_string).collect();
+                    self.stack.push(Value::String(result));
+                }
+                Instruction::MakeClosure(index) => {
+                    let frame = self.frames.last().unwrap();
+                    let closure = Closure {
+                        function: frame.function.functions[index].clone(),
+                        scope: frame.scope.clone(),
+                    };
+                    self.stack
+                        .push(Value::Callable(Callable::Compiled(Rc::new(closure))));
+                }
+                Instruction::PrepareCall(argc) => match self.peek(0) {
+                    Value::Callable(Callable::Compiled(closure))
+                        if closure.function.params != argc =>
+                    {
+                        let err = RuntimeError::InvalidArgCount(argc, closure.function.params);
+                        return Err(self.error(Error::RuntimeError(err)));
+                    }
+                    Value::Callable(_) => {}
+                    value => {
+                        let err = Error::ThisCannotBeCalled(value.to_string());
+                        return Err(self.error(err));
+                    }
+                },
+                Instruction::Call(argc) => {
+                    let args = self.stack.split_off(self.stack.len() - argc);
+                    let Value::Callable(callable) = self.pop() else {
+                        unreachable!("Checked by PrepareCall")
+                    };
+                    let span = self.span();
+                    if let Some(value) = self.call(callable, span, args)? {
+                        self.stack.push(value);
+                    }
+                }
+                Instruction::Return => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    self.places.truncate(frame.places);
+                    if frame.is_call {
+                        self.calls.pop();
+                    }
+                    if let Some(scope) = frame.scope {
+                        release_scope(scope);
+                    }
+                    if self.frames.len() == depth {
+                        return Ok(value);
+                    }
+                    self.stack.push(value);
+                }
+                Instruction::Negate => {
+                    let value = match self.pop() {
+                        Value::Int(num) => match num.checked_neg() {
+                            Some(num) => Value::Int(num),
+                            None => {
+                                let err = Error::RuntimeError(RuntimeError::IntegerOverflow);
+                                return Err(self.error(err));
+                            }
+                        },
+                        Value::Float(num) => Value::Float(-num),
+                        value => return Err(self.error(incompatible_type(&value))),
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::Not => match self.pop() {
+                    Value::Bool(val) => self.stack.push(Value::Bool(!val)),
+                    value => return Err(self.error(incompatible_type(&value))),
+                },
+                Instruction::CheckBool => {
+                    if !matches!(self.peek(0), Value::Bool(_)) {
+                        return Err(self.error(incompatible_type(self.peek(0))));
+                    }
+                }
+                Instruction::Binary(op, spans) => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let (span_a, span_b) = self.function().operand_spans[spans].clone();
+                    let result = apply_math_operator(&a, &b, op, span_a, span_b)?;
+                    self.stack.push(result);
+                }
+                Instruction::Jump(to) => self.jump(to),
+                Instruction::JumpUnlessTrue(to) => {
+                    if !matches!(self.pop(), Value::Bool(true)) {
+                        self.jump(to);
+                    }
+                }
+                Instruction::JumpIfFalse(to) => {
+                    if matches!(self.peek(0), Value::Bool(false)) {
+                        self.jump(to);
+                    }
+                }
+                Instruction::JumpIfTrue(to) => {
+                    if matches!(self.peek(0), Value::Bool(true)) {
+                        self.jump(to);
+                    }
+                }
+                Instruction::EnterLoop { state, next, end } => {
+                    let looping = Loop {
+                        base: self.stack.len() - state,
+                        body: self.stack.len(),
+                        places: self.places.len(),
+                        next,
+                        end,
+                    };
+                    self.frames.last_mut().unwrap().loops.push(looping);
+                }
+                Instruction::ExitLoop => {
+                    let looping = self.frames.last_mut().unwrap().loops.pop().unwrap();
+                    self.stack.truncate(looping.base);
+                    self.stack.push(Value::None);
+                }
+                Instruction::Break => {
+                    let looping = self.frames.last_mut().unwrap().loops.pop().unwrap();
+                    self.stack.truncate(looping.base);
+                    self.places.truncate(looping.places);
+                    self.stack.push(Value::None);
+                    self.jump(looping.end);
+                }
+                Instruction::Continue => {
+                    let frame = self.frames.last_mut().unwrap();
+                    let looping = frame.loops.last().unwrap();
+                    frame.ip = looping.next;
+                    // Values of the unfinished iteration are dropped, the state stays
+                    self.stack.truncate(looping.body);
+                    self.places.truncate(looping.places);
+                }
+                Instruction::LoopCount(exit) => match self.stack.last_mut() {
+                    Some(Value::Int(count)) if *count > 0 => *count -= 1,
+                    _ => self.jump(exit),
+                },
+                Instruction::ForNext(exit) => {
+                    let (Value::Int(i), Value::Int(end)) = (self.peek(2), self.peek(1)) else {
+                        unreachable!("Checked by CheckInt")
+                    };
+                    if i < end {
+                        let i = *i;
+                        self.stack.push(Value::Int(i));
+                    } else {
+                        self.jump(exit);
+                    }
+                }
+                Instruction::ForStep(exit) => {
+                    let len = self.stack.len();
+                    let (Value::Int(i), Value::Int(step)) =
+                        (&self.stack[len - 3], &self.stack[len - 1])
+                    else {
+                        unreachable!("Checked by CheckInt")
+                    };
+                    match i.checked_add(*step) {
+                        Some(next) => self.stack[len - 3] = Value::Int(next),
+                        None => self.jump(exit),
+                    }
+                }
+                Instruction::IntoItems => {
+                    // Items are collected first so the body can modify the collection
+                    let items: Vec<Value> = match self.pop() {
+                        Value::List(list) => list.borrow().clone(),
+                        Value::Object(object) => object
+                            .borrow()
+                            .fields
+                            .keys()
+                            .cloned()
+                            .map(Value::from)
+                            .collect(),
+                        Value::String(str) => {
+                            str.chars().map(|c| Value::String(c.to_string())).collect()
+                        }
+                        _ => return Err(self.error(Error::InvalidForCollection)),
+                    };
+                    self.stack.push(items.into());
+                    self.stack.push(Value::Int(0));
+                }
+                Instruction::ForEachNext(exit) => {
+                    let (Value::List(items), Value::Int(i)) = (self.peek(1), self.peek(0)) else {
+                        unreachable!("Pushed by IntoItems")
+                    };
+                    let item = usize::try_from(*i)
+                        .ok()
+                        .and_then(|i| items.borrow().get(i).cloned());
+                    match item {
+                        Some(item) => {
+                            if let Some(Value::Int(i)) = self.stack.last_mut() {
+                                *i += 1;
+                            }
+                            self.stack.push(item);
+                        }
+                        None => self.jump(exit),
+                    }
+                }
+                Instruction::Fail(failure) => {
+                    let err = match failure {
+                        Failure::BreakOutsideLoop => Error::BreakOutsideLoop,
+                        Failure::ContinueOutsideLoop => Error::ContinueOutsideLoop,
+                        Failure::NotAssignable => Error::ThisIsNotAssignable,
+                    };
+                    return Err(self.error(err));
+                }
+            }
+        }
+    }
+
+    /// Calls a function, compiled functions only get a new frame.
+    /// Returns the result of a native function.
+    fn call(
+        &mut self,
+        callable: Callable,
+        span: Range<usize>,
+        args: Vec<Value>,
+    ) -> Result<Option<Value>, Spanned<Error>> {
+        match callable {
+            Callable::NativeFunc(func) => {
+                let ctx = self.ctx.libctx.get_mut(&func.library).unwrap();
+                let result = (func.func)(ctx, func.this.clone(), args)
+                    .map_err(|err| Spanned::new(Error::RuntimeError(err), span))?;
+                match result {
+                    Value::EvaluatedReturn(value) => Ok(Some(*value)),
+                    value => Ok(Some(value)),
+                }
+            }
+            Callable::Compiled(closure) => {
+                if closure.function.params != args.len() {
+                    let err = RuntimeError::InvalidArgCount(args.len(), closure.function.params);
+                    return Err(Spanned::new(Error::RuntimeError(err), span));
+                }
+                let name = closure.name().unwrap_or("<anonymous>").to_owned();
+                if self.calls.len() >= self.ctx.max_call_depth {
+                    let trace = std::iter::once(name)
+                        .chain(self.calls.iter().rev().map(|frame| frame.name.to_owned()))
+                        .take(STACK_OVERFLOW_TRACE_LEN)
+                        .collect();
+                    return Err(Spanned::new(Error::StackOverflow(trace), span));
+                }
+                let function = closure.function.clone();
+                let mut slots: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+                slots.resize(function.slots.len(), None);
+                let scope = Scope {
+                    slots: RefCell::new(slots),
+                    parent: closure.scope.clone(),
+                };
+                self.calls.push(CallFrame { name, span });
+                self.frames.push(Frame {
+                    function,
+                    ip: 0,
+                    scope: Some(Rc::new(scope)),
+                    base: self.stack.len(),
+                    places: self.places.len(),
+                    loops: vec![],
+                    is_call: true,
+                });
+                Ok(None)
+            }
+            Callable::Function(_) => Err(Spanned::new(
+                Error::ThisCannotBeCalled(callable.to_string()),
+                span,
+            )),
+        }
+    }
+
+    /// Evaluates a property, i.e. calls it without arguments
+    fn property(&mut self, value: Value) -> Result<Value, Spanned<Error>> {
+        let span = self.span();
+        let callable = match value {
+            Value::Callable(callable) => callable,
+            _ => {
+                let err = Error::TypeError(TypeErrorKind::PropertyNotCallable);
+                return Err(self.error(err));
+            }
+        };
+        let depth = self.frames.len();
+        let result = match self.call(callable, 0..0, vec![]) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => self.execute(depth),
+            Err(err) => Err(err),
+        };
+        result.map_err(|err| Spanned::new(err.item, span))
+    }
+
+    /// Reads a field of an object, property fields are evaluated
+    fn get_field(
+        &mut self,
+        object: &ObjectRef,
+        key: &HashableValue,
+    ) -> Result<Value, Spanned<Error>> {
+        let (value, is_prop) = {
+            let object = object.borrow();
+            match object.fields.get(key) {
+                Some(value) => (value.clone(), object.fields_props.contains(key)),
+                None => {
+                    let name = Value::from(key.clone()).to_string();
+                    return Err(self.error(invalid_identifier(name)));
+                }
+            }
+        };
+        match is_prop {
+            true => self.property(value),
+            false => Ok(value),
+        }
+    }
+
+    fn load_variable(&mut self, index: usize) -> Result<Value, Spanned<Error>> {
+        let frame = self.frames.last().unwrap();
+        let var = &frame.function.variables[index];
+        let scope = frame.scope.as_ref().unwrap();
+        let found = var
+            .slot
+            .map(|slot| (0, slot))
+            .into_iter()
+            .chain(var.outer.iter().copied())
+            .find_map(|(depth, slot)| scope.ancestor(depth).slots.borrow()[slot].clone());
+        if let Some(value) = found {
+            return Ok(value);
+        }
+        match &self.globals[var.global] {
+            Some(value) if self.global_props[var.global] => self.property(value.clone()),
+            Some(value) => Ok(value.clone()),
+            None => Err(self.error(invalid_identifier(var.name.to_owned()))),
+        }
+    }
+
+    /// Variables of outer scopes are updated instead of being shadowed,
+    /// new variables are created in the current scope
+    fn store_variable(&mut self, index: usize, value: Value) -> Value {
+        let frame = self.frames.last().unwrap();
+        let var = &frame.function.variables[index];
+        let scope = frame.scope.as_ref().unwrap();
+        for (depth, slot) in var
+            .slot
+            .map(|slot| (0, slot))
+            .into_iter()
+            .chain(var.outer.iter().copied())
+        {
+            let mut slots = scope.ancestor(depth).slots.borrow_mut();
+            if slots[slot].is_some() {
+                return slots[slot].replace(value).unwrap();
+            }
+        }
+        let global = &mut self.globals[var.global];
+        if global.is_some() || var.slot.is_none() {
+            return global.replace(value).unwrap_or(Value::None);
+        }
+        scope.slots.borrow_mut()[var.slot.unwrap()] = Some(value);
+        Value::None
+    }
+
+    fn function(&self) -> &Function {
+        &self.frames.last().unwrap().function
+    }
+
+    fn scope(&self) -> &Rc<Scope> {
+        self.frames.last().unwrap().scope.as_ref().unwrap()
+    }
+
+    fn key(&self, name: usize) -> HashableValue {
+        match &self.function().constants[name] {
+            Value::String(name) => HashableValue::String(name.to_owned()),
+            _ => unreachable!("Field names are strings"),
+        }
+    }
+
+    fn jump(&mut self, to: usize) {
+        self.frames.last_mut().unwrap().ip = to;
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Empty stack")
+    }
+
+    fn peek(&self, depth: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - depth]
+    }
+
+    /// Span of the current instruction
+    fn span(&self) -> Range<usize> {
+        let frame = self.frames.last().unwrap();
+        frame.function.spans[frame.ip - 1].to_owned()
+    }
+
+    fn error(&self, err: Error) -> Spanned<Error> {
+        Spanned::new(err, self.span())
+    }
+}
+
+fn invalid_identifier(name: String) -> Error {
+    Error::RuntimeError(RuntimeError::InvalidIdentifier(name))
+}
+
+fn not_an_object() -> Error {
+    Error::TypeError(TypeErrorKind::NotAnObject)
+}
+
+fn incompatible_type(value: &Value) -> Error {
+    Error::TypeError(TypeErrorKind::Operand(value.type_to_string().to_owned()))
+}
+
+/// Drops the variables of a finished call if only closures stored in it keep it alive
+fn release_scope(scope: Rc<Scope>) {
+    if Rc::strong_count(&scope) == 1 {
+        return;
+    }
+    let mut graph = cycles::Graph::new(&scope);
+    let root = graph.root();
+    for value in scope.slots.borrow().iter().flatten() {
+        graph.value(root, value, &mut |graph, parent, callable| {
+            walk_closure(graph, parent, callable, &scope)
+        });
+    }
+    if !graph.root_is_alive() {
+        let slots = std::mem::take(&mut *scope.slots.borrow_mut());
+        drop(slots);
+    }
+}
+
+/// Walks the scopes that a closure captured inside the released one
+fn walk_closure(
+    graph: &mut cycles::Graph,
+    parent: cycles::NodeId,
+    callable: &Callable,
+    released: &Rc<Scope>,
+) {
+    let Callable::Compiled(closure) = callable else {
+        return;
+    };
+    let Some(id) = graph.reference(parent, closure) else {
+        return;
+    };
+    let mut parent = id;
+    let mut scope = closure.scope.as_ref();
+    // Only scopes nested in the released one can refer to it
+    while let Some(current) = scope.filter(|current| is_nested(current, released)) {
+        let Some(id) = graph.reference(parent, current) else {
+            return;
+        };
+        for value in current.slots.borrow().iter().flatten() {
+            graph.value(id, value, &mut |graph, parent, callable| {
+                walk_closure(graph, parent, callable, released)
+            });
+        }
+        parent = id;
+        scope = current.parent.as_ref();
+    }
+}
+
+fn is_nested(scope: &Rc<Scope>, outer: &Rc<Scope>) -> bool {
+    let mut scope = Some(scope);
+    while let Some(current) = scope {
+        if Rc::ptr_eq(current, outer) {
+            return true;
+        }
+        scope = current.parent.as_ref();
+    }
+    false
+}
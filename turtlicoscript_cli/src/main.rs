@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::{env, fs};
+use turtlicoscript::diagnostics::{self, Diagnostic, Format, Severity};
+use turtlicoscript::locale::Language;
 use turtlicoscript::parser;
 use turtlicoscript::ast::{Spanned, Expression};
 use turtlicoscript_gui::{app::ScriptState, world::WorldCreationData};
@@ -8,16 +10,24 @@ use turtlicoscript_gui::{app::ScriptState, world::WorldCreationData};
 fn run(ast: Spanned<Expression>, src: &String, data: WorldCreationData) {
     let subapp = turtlicoscript_gui::app::ScriptApp::spawn(ast, data, false);
     let state = subapp.program_state.clone();
+    let warnings = subapp.warnings.clone();
 
     turtlicoscript_gui::app::RootApp::run(
         |_ctx|{ vec![
             Box::new(subapp)
         ]}
     );
+    let warnings = warnings.lock().unwrap();
+    if !warnings.is_empty() {
+        let diagnostics = warnings.iter()
+            .map(|warning| Diagnostic::from(warning).with_severity(Severity::Warning))
+            .collect::<Vec<Diagnostic>>();
+        eprintln!("{}", diagnostics::render(&src, &diagnostics, Format::Ansi, Language::default()));
+    }
     let _state = state.lock().unwrap();
     match &*_state {
-        ScriptState::Error(err) => {
-            eprintln!("{}", err.build_message(&src));
+        ScriptState::Error(err, trace) => {
+            eprintln!("{}", err.build_message(&src, trace));
         }
         _ => {}
     }
@@ -38,7 +48,7 @@ fn run(ast: Spanned<Expression>, src: &String, data: WorldCreationData) {
             }
         },
         Err(err) => {
-            eprintln!("{}", err.build_message(&src));
+            eprintln!("{}", err.build_message(&src, ctx.error_trace()));
         }
     }
 }
@@ -69,7 +79,8 @@ fn run_file(file: String) {
             run(ast, &src, data);
         },
         Err(errors) => {
-            eprintln!("File parse error (s):\n{}", errors.into_iter().map(|err| err.build_message(&src)).collect::<Vec<String>>().join("\n"));
+            let diagnostics = errors.iter().map(Diagnostic::from).collect::<Vec<Diagnostic>>();
+            eprintln!("File parse error (s):\n{}", diagnostics::render(&src, &diagnostics, Format::Ansi, Language::default()));
         }
     }
 }
@@ -6,7 +6,10 @@ use std::{
 use egui::Color32;
 use std::sync::{atomic::AtomicBool, mpsc::channel};
 use turtlicoscript::ast::{Expression, Spanned};
-use turtlicoscript::interpreter::CancellationToken;
+use turtlicoscript::diagnostics::Severity;
+use turtlicoscript::error::Error;
+use turtlicoscript::interpreter::{Budget, CancellationToken, Context};
+use turtlicoscript::value::Value;
 use crate::world::WorldCreationData;
 
 use crate::world::World;
@@ -139,9 +142,16 @@ impl eframe::App for RootApp {
 pub enum ScriptState {
     Running,
     Finished,
-    Error(Spanned<turtlicoscript::error::Error>),
+    /// The error and function calls that led to it
+    Error(Spanned<turtlicoscript::error::Error>, Vec<turtlicoscript::error::CallFrame>),
 }
 
+/// Stack size of the thread that evaluates the script,
+/// wasm-build.sh gives the same size to the web worker
+const SCRIPT_STACK_SIZE: usize = 64 * 1024 * 1024;
+/// Call depth that fits into [`SCRIPT_STACK_SIZE`]
+const SCRIPT_MAX_CALL_DEPTH: usize = turtlicoscript::interpreter::max_call_depth(SCRIPT_STACK_SIZE);
+
 pub struct ScriptApp {
     world: Arc<Mutex<World>>,
     windowed: bool,
@@ -149,6 +159,9 @@ pub struct ScriptApp {
     pub thread: Option<JoinHandle<()>>,
     pub cancellable: Option<CancellationToken>,
     pub program_state: Arc<Mutex<ScriptState>>,
+    /// Problems that do not stop the program, e.g. a misspelled variable.
+    /// They are found before the program starts.
+    pub warnings: Arc<Mutex<Vec<Spanned<Error>>>>,
 }
 
 impl ScriptApp {
@@ -160,14 +173,21 @@ impl ScriptApp {
             thread: None,
             cancellable: None,
             program_state: Arc::new(Mutex::new(ScriptState::Running)),
+            warnings: Arc::new(Mutex::new(vec![])),
         }
     }
 
+    pub fn spawn(ast: Spanned<Expression>, data: WorldCreationData, windowed: bool) -> ScriptApp {
+        Self::spawn_with_budget(ast, data, windowed, Budget::default())
+    }
+
+    /// Runs the program with limited number of steps or time
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn spawn(
+    pub fn spawn_with_budget(
         ast: Spanned<Expression>,
         data: WorldCreationData,
         windowed: bool,
+        budget: Budget,
     ) -> ScriptApp {
         let (tx, rx) = channel();
         let world = crate::world::World::new_arc_mutex(tx, data);
@@ -175,31 +195,39 @@ impl ScriptApp {
 
         let mut app = ScriptApp::new(world.clone(), windowed);
         let state = app.program_state.clone();
+        let warnings = app.warnings.clone();
         app.cancellable = Some(cancellable.clone());
 
-        let handle = std::thread::spawn(move || {
-            let mut ctx = turtlicoscript::interpreter::Context::new_parent(Some(cancellable));
-            ctx.import_library(crate::init_library(world, rx), false);
-            match ctx.eval_root(&ast) {
-                Ok(_) => {
-                    let mut _state = state.lock().unwrap();
-                    *_state = ScriptState::Finished;
-                }
-                Err(err) => {
-                    let mut _state = state.lock().unwrap();
-                    *_state = ScriptState::Error(err);
+        let handle = std::thread::Builder::new()
+            .stack_size(SCRIPT_STACK_SIZE)
+            .spawn(move || {
+                let mut ctx = Context::new_parent(Some(cancellable));
+                ctx.max_call_depth = SCRIPT_MAX_CALL_DEPTH;
+                ctx.import_library(crate::init_library(world, rx), false);
+                ctx.set_budget(budget);
+                match run_checked(&mut ctx, &ast, &warnings) {
+                    Ok(_) => {
+                        let mut _state = state.lock().unwrap();
+                        *_state = ScriptState::Finished;
+                    }
+                    Err(err) => {
+                        let mut _state = state.lock().unwrap();
+                        *_state = ScriptState::Error(err, ctx.error_trace().to_vec());
+                    }
                 }
-            }
-        });
+            })
+            .expect("Cannot spawn the script thread");
 
         app.thread = Some(handle);
         app
     }
+    /// Runs the program with limited number of steps or time
     #[cfg(target_arch = "wasm32")]
-    pub fn spawn(
+    pub fn spawn_with_budget(
         ast: Spanned<Expression>,
         data: WorldCreationData,
         windowed: bool,
+        budget: Budget,
     ) -> ScriptApp {
         use web_sys::console;
         let (tx, rx) = channel();
@@ -209,21 +237,24 @@ impl ScriptApp {
 
         let mut app = crate::app::ScriptApp::new(world, windowed);
         let state = app.program_state.clone();
+        let warnings = app.warnings.clone();
         app.cancellable = Some(cancellable.clone());
 
         console::log_1(&"[worker] Starting sub program".into());
         let worker = crate::worker::spawn(move || {
             console::log_1(&"[worker] Hello from sub program".into());
-            let mut ctx = turtlicoscript::interpreter::Context::new_parent(Some(cancellable));
+            let mut ctx = Context::new_parent(Some(cancellable));
+            ctx.max_call_depth = SCRIPT_MAX_CALL_DEPTH;
             ctx.import_library(crate::init_library(world_clone, rx), false);
-            match ctx.eval_root(&ast) {
+            ctx.set_budget(budget);
+            match run_checked(&mut ctx, &ast, &warnings) {
                 Ok(_result) => {
                     let mut _state = state.lock().unwrap();
                     *_state = ScriptState::Finished;
                 }
                 Err(err) => {
                     let mut _state = state.lock().unwrap();
-                    *_state = ScriptState::Error(err);
+                    *_state = ScriptState::Error(err, ctx.error_trace().to_vec());
                 }
             }
         })
@@ -287,3 +318,26 @@ impl SubApp for ScriptApp {
         return !(script_stopped);
     }
 }
+
+/// Runs the program unless static checks find an error, i.e. code that always fails,
+/// so that mistakes are reported before the turtle starts moving.
+/// Warnings do not stop it, they are stored in `warnings` for the editor.
+fn run_checked(
+    ctx: &mut Context,
+    ast: &Spanned<Expression>,
+    warnings: &Mutex<Vec<Spanned<Error>>>,
+) -> Result<Value, Spanned<Error>> {
+    let mut error = None;
+    for problem in ctx.resolve(ast) {
+        match problem.severity {
+            Severity::Error => {
+                error.get_or_insert(problem.error);
+            }
+            Severity::Warning => warnings.lock().unwrap().push(problem.error),
+        }
+    }
+    match error {
+        Some(error) => Err(error),
+        None => ctx.eval_root(ast),
+    }
+}
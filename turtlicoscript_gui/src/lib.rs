@@ -1,7 +1,6 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use std::any::Any;
-use std::collections::HashMap;
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
 
@@ -11,7 +10,7 @@ use turtlicoscript::error::RuntimeError;
 use turtlicoscript::interpreter::Scope;
 use turtlicoscript::value::{
     unwrap_context, FuncThisObject, HashableValue, Library, LibraryContext, NativeFuncArgs,
-    NativeFuncCtxArg, NativeFuncReturn, TSObject, Value,
+    NativeFuncCtxArg, NativeFuncReturn, ObjectFields, TSObject, Value,
 };
 use turtlicoscript::{funcmap, funcmap_obj};
 use world::{SpriteID, World, BLOCK_SIZE_PX};
@@ -67,8 +66,8 @@ impl Context {
         Sprite::set_pos_target(&self.world, &id, x, y);
     }
 
-    pub fn sprite_get_block_xy(&mut self, id: SpriteID) -> HashMap<HashableValue, Value> {
-        let mut res = HashMap::new();
+    pub fn sprite_get_block_xy(&mut self, id: SpriteID) -> ObjectFields {
+        let mut res = ObjectFields::new();
         let mut _world = self.world.lock().unwrap();
         let sprite = _world.sprites.get_mut(&id).unwrap();
         res.insert("x".into(), sprite.get_block_x().into());
@@ -234,7 +233,7 @@ fn get_sprite_id(this: FuncThisObject) -> Result<SpriteID, RuntimeError> {
                 .unwrap()
                 .borrow()
                 .fields
-                .get(&"sprite_id".into())
+                .get(&HashableValue::from("sprite_id"))
             {
                 Some(id) => match id {
                     Value::Int(id) => {
@@ -415,11 +414,11 @@ fn place_destroy_block(
             }
             Value::Object(value) => {
                 let value = value.borrow_mut();
-                match value.fields.get(&("x".into())) {
+                match value.fields.get(&HashableValue::from("x")) {
                     Some(val_x) => x = Some(val_x.try_into()?),
                     None => return Err(RuntimeError::InvalidIdentifier("x".to_owned())),
                 }
-                match value.fields.get(&("y".into())) {
+                match value.fields.get(&HashableValue::from("y")) {
                     Some(val_y) => y = Some(val_y.try_into()?),
                     None => return Err(RuntimeError::InvalidIdentifier("y".to_owned())),
                 }
@@ -33,6 +33,8 @@ for i in "$@"; do
 done
 
 RUSTFLAGS='-C target-feature=+atomics,+bulk-memory,+mutable-globals --cfg=web_sys_unstable_apis'
+# Stack of the worker that runs scripts, same as SCRIPT_STACK_SIZE in turtlicoscript_gui/src/app.rs
+export WASM_BINDGEN_THREADS_STACK_SIZE=$((64 * 1024 * 1024))
 
 RUSTFLAGS=$RUSTFLAGS cargo +nightly build --profile "$PROFILE" --target wasm32-unknown-unknown -Z build-std=std,panic_abort
 